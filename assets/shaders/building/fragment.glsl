@@ -0,0 +1,42 @@
+#version 330 core
+
+in vec3 world_pos;
+in vec3 normal;
+flat in int material;
+
+out vec4 fragColor;
+
+uniform sampler2D atlas;
+uniform vec3 sun_direction;
+uniform float texture_scale;
+uniform vec2 atlas_grid;
+uniform float atlas_padding;
+uniform vec4 tint;
+uniform float material_layers[4];
+
+const float ambient = 0.3;
+
+// Samples a repeating tile of the atlas, like the terrain shader
+vec4 sample_layer(float layer, vec2 uv) {
+    vec2 cell = vec2(mod(layer, atlas_grid.x), floor(layer / atlas_grid.x));
+    vec2 inner = atlas_padding + fract(uv) * (1.0 - 2.0 * atlas_padding);
+    vec2 scale = (1.0 - 2.0 * atlas_padding) / atlas_grid;
+    return textureGrad(atlas, (cell + inner) / atlas_grid, dFdx(uv) * scale, dFdy(uv) * scale);
+}
+
+void main() {
+    vec3 n = normalize(normal);
+
+    // Pieces are flat faced, so projecting along the dominant axis is enough
+    vec3 axis = abs(n);
+    vec3 uvw = world_pos * texture_scale;
+    vec2 uv = axis.x > axis.y && axis.x > axis.z ? uvw.zy
+        : axis.y > axis.z ? uvw.xz
+        : uvw.xy;
+    vec4 tex_color = sample_layer(material_layers[material], uv);
+
+    float diffuse = max(dot(n, sun_direction), 0.0);
+    float light = ambient + (1.0 - ambient) * diffuse;
+
+    fragColor = vec4(tex_color.rgb * light, tex_color.a) * tint;
+}
@@ -0,0 +1,19 @@
+#version 330 core
+
+layout(location = 0) in vec3 in_pos;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in float in_material;
+
+out vec3 world_pos;
+out vec3 normal;
+flat out int material;
+
+uniform mat4 mvp;
+
+void main() {
+    world_pos = in_pos;
+    normal = in_normal;
+    material = int(in_material + 0.5);
+
+    gl_Position = mvp * vec4(in_pos, 1.0);
+}
@@ -1,19 +1,70 @@
 #version 330 core
 
-in vec2 texcoord;
+in vec3 world_pos;
+in vec3 normal;
+in vec2 tex_coord;
+flat in int biome;
+flat in int material;
 
 out vec4 fragColor;
 
-uniform sampler2D tex;
+uniform sampler2D atlas;
+uniform vec3 sun_direction;
+uniform float texture_scale;
+uniform float use_tex_coords;
+uniform vec2 atlas_grid;
+uniform float atlas_padding;
+uniform float material_layers[6];
+
+const float ambient = 0.3;
+
+const int MATERIAL_GRASS = 0;
+
+// Grass tint per biome id, in the order of `Biome::ALL`
+const vec3 grass_tints[5] = vec3[5](
+    vec3(1.0, 1.0, 1.0), // plains
+    vec3(0.8, 0.9, 0.7), // hills
+    vec3(1.2, 1.1, 0.7), // desert
+    vec3(0.9, 1.0, 1.0), // snow
+    vec3(0.6, 0.7, 0.5)  // swamp
+);
+
+// Samples a repeating tile of the atlas. Gradients come from the unwrapped
+// coordinates, so the wrap at tile edges doesn't drop to the smallest mip
+vec4 sample_layer(float layer, vec2 uv) {
+    vec2 cell = vec2(mod(layer, atlas_grid.x), floor(layer / atlas_grid.x));
+    vec2 inner = atlas_padding + fract(uv) * (1.0 - 2.0 * atlas_padding);
+    vec2 scale = (1.0 - 2.0 * atlas_padding) / atlas_grid;
+    return textureGrad(atlas, (cell + inner) / atlas_grid, dFdx(uv) * scale, dFdy(uv) * scale);
+}
+
+// Samples the texture along all three axes and blends by the normal, so
+// cliffs and overhangs aren't stretched like with a single top-down projection
+vec4 triplanar(float layer, vec3 pos, vec3 n) {
+    vec3 weights = pow(abs(n), vec3(4.0));
+    weights /= weights.x + weights.y + weights.z;
+
+    vec3 uvw = pos * texture_scale;
+    return sample_layer(layer, uvw.zy) * weights.x
+        + sample_layer(layer, uvw.xz) * weights.y
+        + sample_layer(layer, uvw.xy) * weights.z;
+}
 
 void main() {
-    vec4 tex_color = texture(tex, texcoord);
-    
-    // Create a variation factor based on texcoord
-    float variation = 0.5 + 0.1 * sin(texcoord.x * 2.0 + texcoord.y * 2.0);
-    
-    // Apply the variation to darken the texture
-    vec4 varied_color = tex_color;
-    
-    fragColor = varied_color;
+    vec3 n = normalize(normal);
+    float layer = material_layers[material];
+    vec4 tex_color = use_tex_coords > 0.5
+        ? sample_layer(layer, tex_coord * texture_scale)
+        : triplanar(layer, world_pos, n);
+
+    vec3 albedo = tex_color.rgb;
+    if (material == MATERIAL_GRASS) {
+        albedo = min(albedo * grass_tints[biome], vec3(1.0));
+    }
+
+    // Lambert diffuse from the sun on top of a flat ambient term
+    float diffuse = max(dot(n, sun_direction), 0.0);
+    float light = ambient + (1.0 - ambient) * diffuse;
+
+    fragColor = vec4(albedo * light, tex_color.a);
 }
@@ -1,15 +1,26 @@
 #version 330 core
 
 layout(location = 0) in vec3 in_pos;
-layout(location = 1) in vec2 in_tex_coord;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in vec2 in_tex_coord;
+layout(location = 3) in float in_biome;
+layout(location = 4) in float in_material;
 
-out vec2 texcoord;
+out vec3 world_pos;
+out vec3 normal;
+out vec2 tex_coord;
+flat out int biome;
+flat out int material;
 
 uniform mat4 mvp;
 uniform float time; // Time uniform for animation
 
 void main() {
-    texcoord = in_tex_coord;
+    world_pos = in_pos;
+    normal = in_normal;
+    tex_coord = in_tex_coord;
+    biome = int(in_biome + 0.5);
+    material = int(in_material + 0.5);
 
     gl_Position = mvp * vec4(in_pos, 1.0);
 }
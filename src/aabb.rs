@@ -0,0 +1,72 @@
+use glam::Vec3;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// Smallest box containing all the points.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Aabb {
+        points.into_iter().fold(
+            Aabb::new(Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |aabb, point| Aabb::new(aabb.min.min(point), aabb.max.max(point)),
+        )
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|corner| {
+            Vec3::new(
+                if corner & 1 == 0 {
+                    self.min.x
+                } else {
+                    self.max.x
+                },
+                if corner & 2 == 0 {
+                    self.min.y
+                } else {
+                    self.max.y
+                },
+                if corner & 4 == 0 {
+                    self.min.z
+                } else {
+                    self.max.z
+                },
+            )
+        })
+    }
+
+    /// Entry distance and face normal of a ray hitting the box from outside,
+    /// using the slab method. `direction` must be normalised.
+    pub fn ray_intersection(&self, origin: Vec3, direction: Vec3) -> Option<(f32, Vec3)> {
+        let inverse = direction.recip();
+        let t1 = (self.min - origin) * inverse;
+        let t2 = (self.max - origin) * inverse;
+        let near = t1.min(t2);
+        let far = t1.max(t2);
+
+        let entry = near.max_element();
+        let exit = far.min_element();
+        if entry > exit || entry < 0.0 {
+            return None;
+        }
+
+        // The face the ray enters through is on the axis with the latest entry
+        let axis = if entry == near.x {
+            0
+        } else if entry == near.y {
+            1
+        } else {
+            2
+        };
+        let mut normal = Vec3::ZERO;
+        normal[axis] = -direction[axis].signum();
+        Some((entry, normal))
+    }
+}
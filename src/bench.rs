@@ -0,0 +1,32 @@
+use crate::marching_cubes::{generate_marching_cubes, FIELD_PADDING};
+use crate::scalar_generator::generate_scalar_field;
+use crate::world_config::WorldGenConfig;
+use glam::IVec3;
+use std::time::Instant;
+
+const GRID_SIZES: [usize; 3] = [128, 256, 512];
+
+/// Times scalar field generation and meshing at a few grid sizes.
+/// Run with `cargo run --release -- bench`.
+pub fn run(config: &WorldGenConfig) {
+    for cells in GRID_SIZES {
+        let grid_size = cells + 1 + 2 * FIELD_PADDING;
+
+        let start = Instant::now();
+        let scalar_field = generate_scalar_field(config, [grid_size; 3], IVec3::ZERO, 1);
+        let generation_time = start.elapsed();
+
+        let start = Instant::now();
+        let (vertices, indices) = generate_marching_cubes(&scalar_field, cells, 0.9);
+        let meshing_time = start.elapsed();
+
+        println!(
+            "{}³: generation {:.2}ms, meshing {:.2}ms, {} vertices, {} indices",
+            cells,
+            generation_time.as_secs_f64() * 1000.0,
+            meshing_time.as_secs_f64() * 1000.0,
+            vertices.len(),
+            indices.len()
+        );
+    }
+}
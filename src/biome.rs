@@ -0,0 +1,147 @@
+use crate::density::NoiseKind;
+use noise::{NoiseFn, Perlin};
+
+/// Frequency of the temperature and humidity noise. Low, so biomes span
+/// several chunks.
+const CLIMATE_FREQUENCY: f64 = 0.0015;
+/// How far apart two climates can be and still blend, in climate space.
+const BLEND_WIDTH: f64 = 0.2;
+/// Biomes with less weight than this are skipped when blending.
+pub const MIN_BLEND_WEIGHT: f64 = 0.001;
+
+pub const BIOME_COUNT: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Biome {
+    Plains,
+    Hills,
+    Desert,
+    Snow,
+    Swamp,
+}
+
+/// How a biome shapes the terrain, relative to the world config.
+pub struct BiomeTerrain {
+    pub noise: NoiseKind,
+    pub amplitude: f64,
+    pub frequency_multiplier: f64,
+    /// Raises or lowers the ground height, before `global_scale`.
+    pub ground_offset: f64,
+    pub falloff_multiplier: f64,
+}
+
+impl Biome {
+    pub const ALL: [Biome; BIOME_COUNT] = [
+        Biome::Plains,
+        Biome::Hills,
+        Biome::Desert,
+        Biome::Snow,
+        Biome::Swamp,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Biome::Plains => "plains",
+            Biome::Hills => "hills",
+            Biome::Desert => "desert",
+            Biome::Snow => "snow",
+            Biome::Swamp => "swamp",
+        }
+    }
+
+    /// Temperature and humidity this biome is centred on, both in `-1..1`.
+    fn climate(self) -> (f64, f64) {
+        match self {
+            Biome::Plains => (0.0, 0.0),
+            Biome::Hills => (-0.1, -0.35),
+            Biome::Desert => (0.4, -0.3),
+            Biome::Snow => (-0.4, 0.05),
+            Biome::Swamp => (0.3, 0.35),
+        }
+    }
+
+    pub fn terrain(self) -> BiomeTerrain {
+        match self {
+            Biome::Plains => BiomeTerrain {
+                noise: NoiseKind::Perlin,
+                amplitude: 0.6,
+                frequency_multiplier: 1.0,
+                ground_offset: 0.0,
+                falloff_multiplier: 1.0,
+            },
+            Biome::Hills => BiomeTerrain {
+                noise: NoiseKind::Fbm,
+                amplitude: 1.2,
+                frequency_multiplier: 0.8,
+                ground_offset: 10.0,
+                falloff_multiplier: 0.8,
+            },
+            Biome::Desert => BiomeTerrain {
+                noise: NoiseKind::Simplex,
+                amplitude: 0.4,
+                frequency_multiplier: 1.5,
+                ground_offset: -5.0,
+                falloff_multiplier: 1.2,
+            },
+            Biome::Snow => BiomeTerrain {
+                noise: NoiseKind::Ridged,
+                amplitude: 1.5,
+                frequency_multiplier: 0.6,
+                ground_offset: 30.0,
+                falloff_multiplier: 0.7,
+            },
+            Biome::Swamp => BiomeTerrain {
+                noise: NoiseKind::Perlin,
+                amplitude: 0.2,
+                frequency_multiplier: 2.0,
+                ground_offset: -12.0,
+                falloff_multiplier: 1.5,
+            },
+        }
+    }
+}
+
+/// Low-frequency temperature and humidity noise that decides the biome of every
+/// world column.
+pub struct ClimateMap {
+    temperature: Perlin,
+    humidity: Perlin,
+}
+
+impl ClimateMap {
+    pub fn new(world_seed: u32) -> ClimateMap {
+        ClimateMap {
+            temperature: Perlin::new(world_seed.wrapping_add(101)),
+            humidity: Perlin::new(world_seed.wrapping_add(202)),
+        }
+    }
+
+    /// Blend weight of every biome at a world column, summing to one. Weights
+    /// fall off smoothly with the distance to each biome's climate, so terrain
+    /// blends across borders instead of forming cliffs.
+    pub fn weights(&self, x: f64, z: f64) -> [f64; BIOME_COUNT] {
+        let point = [x * CLIMATE_FREQUENCY, z * CLIMATE_FREQUENCY];
+        let temperature = self.temperature.get(point);
+        let humidity = self.humidity.get(point);
+
+        let mut weights = [0.0; BIOME_COUNT];
+        for (weight, biome) in weights.iter_mut().zip(Biome::ALL) {
+            let (biome_temperature, biome_humidity) = biome.climate();
+            let distance_squared =
+                (temperature - biome_temperature).powi(2) + (humidity - biome_humidity).powi(2);
+            *weight = (-distance_squared / (BLEND_WIDTH * BLEND_WIDTH)).exp();
+        }
+
+        let total: f64 = weights.iter().sum();
+        weights.map(|weight| weight / total)
+    }
+
+    pub fn biome(&self, x: f64, z: f64) -> Biome {
+        let weights = self.weights(x, z);
+        let dominant = (0..BIOME_COUNT)
+            .max_by(|&a, &b| weights[a].total_cmp(&weights[b]))
+            .unwrap();
+        Biome::ALL[dominant]
+    }
+}
@@ -0,0 +1,277 @@
+use crate::material::Material;
+use crate::raycast::IsoSurface;
+use crate::scalar_field::ScalarField;
+use glam::{IVec3, Vec3};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Material given to samples that a brush turns solid.
+const FILL_MATERIAL: Material = Material::Dirt;
+
+/// Offsets averaged by the smooth brush: the position itself and its six
+/// direct neighbours.
+const NEIGHBOURHOOD: [IVec3; 7] = [
+    IVec3::ZERO,
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    /// Round brush, strongest in the centre.
+    Sphere,
+    /// Axis-aligned box with uniform strength.
+    Cube,
+    /// Blends every sample towards the average of its neighbours instead of
+    /// adding or removing material.
+    Smooth,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushMode {
+    Add,
+    Subtract,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    pub shape: BrushShape,
+    pub radius: f32,
+    /// Density added or removed at full strength, per application.
+    pub strength: f32,
+}
+
+/// A single application of a brush, kept so edits can be replayed on chunks
+/// that are generated later.
+#[derive(Clone, Debug)]
+pub struct BrushStroke {
+    pub brush: Brush,
+    pub center: Vec3,
+    pub mode: BrushMode,
+    /// Density the smooth brush blends towards at every integer world position
+    /// it reaches. Worked out once from world-space samples, so chunks sharing
+    /// a sample, or generated later, all blend it the same way.
+    smoothed: Option<Arc<HashMap<IVec3, f32>>>,
+}
+
+impl BrushStroke {
+    pub fn new(brush: Brush, center: Vec3, mode: BrushMode) -> BrushStroke {
+        BrushStroke {
+            brush,
+            center,
+            mode,
+            smoothed: None,
+        }
+    }
+
+    /// Samples the terrain around a smooth stroke before it is applied. Each
+    /// position blends towards the average of itself and its six neighbours one
+    /// world unit away, wherever the surface has density. Other shapes don't
+    /// need it.
+    pub fn sample_smoothing(&mut self, surface: &impl IsoSurface) {
+        if self.brush.shape != BrushShape::Smooth {
+            return;
+        }
+
+        let min = (self.center - self.brush.radius).floor().as_ivec3();
+        let max = (self.center + self.brush.radius).ceil().as_ivec3();
+        let mut smoothed = HashMap::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let position = IVec3::new(x, y, z);
+                    let mut total = 0.0;
+                    let mut count = 0.0;
+                    for offset in NEIGHBOURHOOD {
+                        if let Some(density) = surface.density((position + offset).as_vec3()) {
+                            total += density;
+                            count += 1.0;
+                        }
+                    }
+                    if count > 0.0 {
+                        smoothed.insert(position, total / count);
+                    }
+                }
+            }
+        }
+        self.smoothed = Some(Arc::new(smoothed));
+    }
+
+    /// Whether the stroke reaches any sample of the field.
+    pub fn touches(&self, scalar_field: &ScalarField) -> bool {
+        let (min, max) = self.sample_bounds(scalar_field);
+        min.cmple(max).all()
+    }
+
+    /// Applies the stroke to the field. `threshold` is the surface density, used
+    /// to give newly solid samples a material.
+    pub fn apply(&self, scalar_field: &mut ScalarField, threshold: f32) {
+        let (min, max) = self.sample_bounds(scalar_field);
+        if !min.cmple(max).all() {
+            return;
+        }
+
+        let sign = match self.mode {
+            BrushMode::Add => 1.0,
+            BrushMode::Subtract => -1.0,
+        };
+
+        let mut updates = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let sample = [x as usize, y as usize, z as usize];
+                    let world = scalar_field.world_position(sample).as_vec3();
+                    let weight = self.weight(world);
+                    if weight <= 0.0 {
+                        continue;
+                    }
+
+                    let old = scalar_field.get(sample[0], sample[1], sample[2]);
+                    let new = match self.brush.shape {
+                        BrushShape::Sphere | BrushShape::Cube => {
+                            old + sign * self.brush.strength * weight
+                        }
+                        BrushShape::Smooth => {
+                            let world = scalar_field.world_position(sample);
+                            let Some(&average) = self
+                                .smoothed
+                                .as_ref()
+                                .and_then(|smoothed| smoothed.get(&world))
+                            else {
+                                continue;
+                            };
+                            old + (average - old) * (self.brush.strength * weight).min(1.0)
+                        }
+                    };
+                    updates.push((sample, old, new));
+                }
+            }
+        }
+
+        for ([x, y, z], old, new) in updates {
+            scalar_field.set(x, y, z, new);
+            if old < threshold && new >= threshold {
+                scalar_field.set_material(x, y, z, FILL_MATERIAL);
+            }
+        }
+    }
+
+    /// Strength of the brush at a world position, in `0..1`.
+    fn weight(&self, world: Vec3) -> f32 {
+        let offset = world - self.center;
+        match self.brush.shape {
+            BrushShape::Sphere | BrushShape::Smooth => {
+                let t = (1.0 - offset.length() / self.brush.radius).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            }
+            BrushShape::Cube => {
+                if offset.abs().max_element() <= self.brush.radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// Inclusive range of field samples inside the brush bounds. Empty when
+    /// `min > max` on any axis.
+    fn sample_bounds(&self, scalar_field: &ScalarField) -> (IVec3, IVec3) {
+        let dimensions = scalar_field.dimensions();
+        let last = IVec3::new(
+            dimensions[0] as i32 - 1,
+            dimensions[1] as i32 - 1,
+            dimensions[2] as i32 - 1,
+        );
+        let origin = scalar_field.origin().as_vec3();
+        let step = scalar_field.step() as f32;
+        let min = ((self.center - self.brush.radius - origin) / step)
+            .floor()
+            .as_ivec3();
+        let max = ((self.center + self.brush.radius - origin) / step)
+            .ceil()
+            .as_ivec3();
+        (min.max(IVec3::ZERO), max.min(last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biome::Biome;
+
+    const SIZE: usize = 10;
+
+    /// Padded field of a chunk of `SIZE - 3` cells with a wavy density.
+    fn chunk_field(origin: IVec3) -> ScalarField {
+        let mut field = ScalarField::from_data(
+            [SIZE; 3],
+            origin,
+            1,
+            vec![0.0; SIZE * SIZE * SIZE],
+            vec![Biome::Plains; SIZE * SIZE],
+        );
+        field.update_parallel(|_, world, _| {
+            let p = world.as_vec3();
+            (p.x * 0.7).sin() + (p.y * 1.3).cos() * (p.z * 0.4).sin()
+        });
+        field
+    }
+
+    /// Two neighbouring chunks, as seen by the chunk manager.
+    struct Chunks([ScalarField; 2]);
+
+    impl IsoSurface for Chunks {
+        /// Density of the exact sample at an integer position.
+        fn density(&self, position: Vec3) -> Option<f32> {
+            let field = &self.0[(position.x >= 7.0) as usize];
+            let local = (position.as_ivec3() - field.origin()).to_array();
+            if local.iter().any(|&i| i < 0 || i >= SIZE as i32) {
+                return None;
+            }
+            Some(field.get(local[0] as usize, local[1] as usize, local[2] as usize))
+        }
+
+        fn material(&self, _: Vec3) -> Option<Material> {
+            None
+        }
+    }
+
+    #[test]
+    fn smoothing_keeps_shared_samples_identical() {
+        let mut chunks = Chunks([
+            chunk_field(IVec3::splat(-1)),
+            chunk_field(IVec3::new(6, -1, -1)),
+        ]);
+        let brush = Brush {
+            shape: BrushShape::Smooth,
+            radius: 4.0,
+            strength: 0.8,
+        };
+        // Centred on the face the two chunks share
+        let mut stroke = BrushStroke::new(brush, Vec3::new(7.0, 3.5, 3.5), BrushMode::Add);
+        stroke.sample_smoothing(&chunks);
+        for field in &mut chunks.0 {
+            stroke.apply(field, 0.0);
+        }
+
+        // World x 6..=8 is in both fields: local x + 1 on the left, x - 6 on the right
+        let [left, right] = &chunks.0;
+        let original = chunk_field(IVec3::splat(-1));
+        let mut changed = 0;
+        for x in 6..=8 {
+            for y in 0..SIZE {
+                for z in 0..SIZE {
+                    assert_eq!(left.get(x + 1, y, z), right.get(x - 6, y, z));
+                    changed += (left.get(x + 1, y, z) != original.get(x + 1, y, z)) as u32;
+                }
+            }
+        }
+        assert!(changed > 0);
+    }
+}
@@ -0,0 +1,501 @@
+use crate::aabb::Aabb;
+use crate::snapping::{BuildGrid, SnapTarget};
+use crate::structure::solve_support;
+use glam::Vec3;
+use miniquad::*;
+
+/// Edge length of floors and walls, in world units.
+pub const PIECE_SIZE: f32 = 4.0;
+/// Thickness of floors and walls.
+const PIECE_THICKNESS: f32 = 0.25;
+const PILLAR_WIDTH: f32 = 0.6;
+
+pub const PIECE_MATERIAL_COUNT: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PieceKind {
+    Floor,
+    Wall,
+    Ramp,
+    Pillar,
+    Window,
+    DoorFrame,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 6] = [
+        PieceKind::Floor,
+        PieceKind::Wall,
+        PieceKind::Ramp,
+        PieceKind::Pillar,
+        PieceKind::Window,
+        PieceKind::DoorFrame,
+    ];
+
+    /// Walls and the wall-shaped window and door frame, which sit on cell edges.
+    pub fn is_wall(self) -> bool {
+        matches!(
+            self,
+            PieceKind::Wall | PieceKind::Window | PieceKind::DoorFrame
+        )
+    }
+
+    /// Solid parts of the piece without rotation, relative to its anchor at the
+    /// bottom centre. Walls span the x axis.
+    fn local_boxes(self) -> Vec<Aabb> {
+        let half = PIECE_SIZE / 2.0;
+        let half_thickness = PIECE_THICKNESS / 2.0;
+        // Wall section from `x0` to `x1` and `y0` to `y1`
+        let wall = |x0: f32, x1: f32, y0: f32, y1: f32| {
+            Aabb::new(
+                Vec3::new(x0, y0, -half_thickness),
+                Vec3::new(x1, y1, half_thickness),
+            )
+        };
+
+        match self {
+            PieceKind::Floor => vec![Aabb::new(
+                Vec3::new(-half, 0.0, -half),
+                Vec3::new(half, PIECE_THICKNESS, half),
+            )],
+            PieceKind::Wall => vec![wall(-half, half, 0.0, PIECE_SIZE)],
+            // Only used for picking; the mesh is a wedge
+            PieceKind::Ramp => vec![Aabb::new(
+                Vec3::new(-half, 0.0, -half),
+                Vec3::new(half, PIECE_SIZE, half),
+            )],
+            PieceKind::Pillar => vec![Aabb::new(
+                Vec3::new(-PILLAR_WIDTH / 2.0, 0.0, -PILLAR_WIDTH / 2.0),
+                Vec3::new(PILLAR_WIDTH / 2.0, PIECE_SIZE, PILLAR_WIDTH / 2.0),
+            )],
+            PieceKind::Window => vec![
+                wall(-half, half, 0.0, 1.5),
+                wall(-half, half, 3.0, PIECE_SIZE),
+                wall(-half, -1.0, 1.5, 3.0),
+                wall(1.0, half, 1.5, 3.0),
+            ],
+            PieceKind::DoorFrame => vec![
+                wall(-half, -0.8, 0.0, 3.0),
+                wall(0.8, half, 0.0, 3.0),
+                wall(-half, half, 3.0, PIECE_SIZE),
+            ],
+        }
+    }
+}
+
+/// What a building piece is made of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PieceMaterial {
+    Wood,
+    Stone,
+    Metal,
+    Glass,
+}
+
+impl PieceMaterial {
+    pub const ALL: [PieceMaterial; PIECE_MATERIAL_COUNT] = [
+        PieceMaterial::Wood,
+        PieceMaterial::Stone,
+        PieceMaterial::Metal,
+        PieceMaterial::Glass,
+    ];
+
+    /// Name of the material in the material manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            PieceMaterial::Wood => "wood",
+            PieceMaterial::Stone => "stone",
+            PieceMaterial::Metal => "metal",
+            PieceMaterial::Glass => "glass",
+        }
+    }
+
+    pub fn next(self) -> PieceMaterial {
+        PieceMaterial::ALL[(self as usize + 1) % PIECE_MATERIAL_COUNT]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Piece {
+    pub kind: PieceKind,
+    pub material: PieceMaterial,
+    /// World position of the bottom centre.
+    pub position: Vec3,
+    /// Quarter turns around the y axis.
+    pub rotation: u8,
+}
+
+impl Piece {
+    /// Solid parts of the piece in world space.
+    pub fn boxes(&self) -> Vec<Aabb> {
+        self.kind
+            .local_boxes()
+            .into_iter()
+            .map(|local| {
+                Aabb::from_points(local.corners().map(|corner| self.local_to_world(corner)))
+            })
+            .collect()
+    }
+
+    fn local_to_world(&self, local: Vec3) -> Vec3 {
+        self.position + rotate_quarter_turns(local, self.rotation)
+    }
+
+    fn append_mesh(&self, vertices: &mut Vec<PieceVertex>, indices: &mut Vec<u32>) {
+        let material = self.material as u8 as f32;
+
+        if self.kind == PieceKind::Ramp {
+            // Wedge rising towards +z
+            let half = PIECE_SIZE / 2.0;
+            let [a, b, c, d, e, f] = [
+                Vec3::new(-half, 0.0, -half),
+                Vec3::new(half, 0.0, -half),
+                Vec3::new(half, 0.0, half),
+                Vec3::new(-half, 0.0, half),
+                Vec3::new(-half, PIECE_SIZE, half),
+                Vec3::new(half, PIECE_SIZE, half),
+            ]
+            .map(|corner| self.local_to_world(corner));
+            let normal = |local: Vec3| rotate_quarter_turns(local.normalize(), self.rotation);
+
+            push_polygon(vertices, indices, &[a, b, c, d], -Vec3::Y, material);
+            push_polygon(vertices, indices, &[d, c, f, e], normal(Vec3::Z), material);
+            push_polygon(
+                vertices,
+                indices,
+                &[a, b, f, e],
+                normal(Vec3::new(0.0, 1.0, -1.0)),
+                material,
+            );
+            push_polygon(vertices, indices, &[a, d, e], normal(-Vec3::X), material);
+            push_polygon(vertices, indices, &[b, c, f], normal(Vec3::X), material);
+            return;
+        }
+
+        // Quarter turns keep boxes axis aligned, so the world boxes can be meshed directly
+        for aabb in self.boxes() {
+            let [p000, p100, p010, p110, p001, p101, p011, p111] = aabb.corners();
+            push_polygon(
+                vertices,
+                indices,
+                &[p000, p001, p011, p010],
+                -Vec3::X,
+                material,
+            );
+            push_polygon(
+                vertices,
+                indices,
+                &[p100, p110, p111, p101],
+                Vec3::X,
+                material,
+            );
+            push_polygon(
+                vertices,
+                indices,
+                &[p000, p100, p101, p001],
+                -Vec3::Y,
+                material,
+            );
+            push_polygon(
+                vertices,
+                indices,
+                &[p010, p011, p111, p110],
+                Vec3::Y,
+                material,
+            );
+            push_polygon(
+                vertices,
+                indices,
+                &[p000, p010, p110, p100],
+                -Vec3::Z,
+                material,
+            );
+            push_polygon(
+                vertices,
+                indices,
+                &[p001, p101, p111, p011],
+                Vec3::Z,
+                material,
+            );
+        }
+    }
+}
+
+/// Rotates around the y axis by quarter turns, exactly.
+fn rotate_quarter_turns(point: Vec3, quarter_turns: u8) -> Vec3 {
+    match quarter_turns % 4 {
+        0 => point,
+        1 => Vec3::new(point.z, point.y, -point.x),
+        2 => Vec3::new(-point.x, point.y, -point.z),
+        _ => Vec3::new(-point.z, point.y, point.x),
+    }
+}
+
+/// Adds a flat convex polygon as a triangle fan.
+fn push_polygon(
+    vertices: &mut Vec<PieceVertex>,
+    indices: &mut Vec<u32>,
+    corners: &[Vec3],
+    normal: Vec3,
+    material: f32,
+) {
+    let first = vertices.len() as u32;
+    vertices.extend(corners.iter().map(|corner| PieceVertex {
+        pos: corner.to_array(),
+        normal: normal.to_array(),
+        material,
+    }));
+    for i in 1..corners.len() as u32 - 1 {
+        indices.extend([first, first + i, first + i + 1]);
+    }
+}
+
+#[repr(C)]
+pub struct PieceVertex {
+    pos: [f32; 3],
+    normal: [f32; 3],
+    /// Piece material id.
+    material: f32,
+}
+
+/// GPU buffers of a set of pieces.
+pub struct PieceMesh {
+    pub vertex_buffer: BufferId,
+    pub index_buffer: BufferId,
+    pub index_count: i32,
+    vertex_capacity: usize,
+    index_capacity: usize,
+}
+
+/// A building piece under a ray.
+#[derive(Clone, Copy, Debug)]
+pub struct PieceHit {
+    /// Index of the piece in `Building::pieces`.
+    pub index: usize,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+/// Every placed building piece, plus the ghost preview of the next one.
+pub struct Building {
+    pieces: Vec<Piece>,
+    /// Grid pieces snap to, set by the first floor.
+    grid: Option<BuildGrid>,
+    ghost: Option<Piece>,
+    mesh: Option<PieceMesh>,
+    ghost_mesh: Option<PieceMesh>,
+    pieces_changed: bool,
+    ghost_changed: bool,
+    /// Bumped whenever the pieces change, so results worked out from them can
+    /// be cached.
+    revision: u64,
+}
+
+impl Building {
+    pub fn new() -> Building {
+        Building {
+            pieces: Vec::new(),
+            grid: None,
+            ghost: None,
+            mesh: None,
+            ghost_mesh: None,
+            pieces_changed: false,
+            ghost_changed: false,
+            revision: 0,
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn piece(&self, index: usize) -> Piece {
+        self.pieces[index]
+    }
+
+    pub fn place(&mut self, piece: Piece) {
+        if self.grid.is_none() && piece.kind == PieceKind::Floor {
+            self.grid = Some(BuildGrid::new(piece.position));
+        }
+        self.pieces.push(piece);
+        self.pieces_changed = true;
+        self.revision += 1;
+    }
+
+    pub fn remove(&mut self, index: usize) -> Piece {
+        self.pieces_changed = true;
+        self.revision += 1;
+        let piece = self.pieces.remove(index);
+        // The next foundation starts a new grid
+        if self.pieces.is_empty() {
+            self.grid = None;
+        }
+        piece
+    }
+
+    /// Whether `piece` would have any support if it was placed now.
+    pub fn would_be_supported(&self, piece: Piece, grounded: impl Fn(&Piece) -> bool) -> bool {
+        let mut pieces = self.pieces.clone();
+        pieces.push(piece);
+        solve_support(&pieces, grounded)[pieces.len() - 1] > 0
+    }
+
+    /// Removes every piece that no longer has a path to the ground, and returns
+    /// how many collapsed.
+    pub fn collapse_unsupported(&mut self, grounded: impl Fn(&Piece) -> bool) -> usize {
+        let support = solve_support(&self.pieces, grounded);
+        let count = self.pieces.len();
+        let mut support = support.into_iter();
+        self.pieces.retain(|_| support.next() != Some(0));
+
+        let collapsed = count - self.pieces.len();
+        if collapsed > 0 {
+            self.pieces_changed = true;
+            self.revision += 1;
+            if self.pieces.is_empty() {
+                self.grid = None;
+            }
+        }
+        collapsed
+    }
+
+    /// Where `placement` goes when aimed at `target`. Snaps to the building grid
+    /// unless `free` is set or there is no foundation yet.
+    pub fn snap(&self, placement: &Placement, target: &SnapTarget, free: bool) -> Piece {
+        match self.grid {
+            Some(grid) if !free => grid.snap(placement, target),
+            _ => placement.piece_at(target.position),
+        }
+    }
+
+    pub fn ghost(&self) -> Option<Piece> {
+        self.ghost
+    }
+
+    pub fn set_ghost(&mut self, ghost: Option<Piece>) {
+        if self.ghost != ghost {
+            self.ghost = ghost;
+            self.ghost_changed = true;
+        }
+    }
+
+    /// Nearest placed piece hit by a ray, within `max_distance`.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<PieceHit> {
+        let direction = direction.normalize_or_zero();
+        let mut nearest: Option<PieceHit> = None;
+        for (index, piece) in self.pieces.iter().enumerate() {
+            for aabb in piece.boxes() {
+                let Some((distance, normal)) = aabb.ray_intersection(origin, direction) else {
+                    continue;
+                };
+                if distance <= max_distance && nearest.is_none_or(|hit| distance < hit.distance) {
+                    nearest = Some(PieceHit {
+                        index,
+                        position: origin + direction * distance,
+                        normal,
+                        distance,
+                    });
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Re-uploads the meshes of the placed pieces and the ghost if they changed.
+    pub fn upload(&mut self, ctx: &mut dyn RenderingBackend) {
+        if self.pieces_changed {
+            update_mesh(ctx, &mut self.mesh, &self.pieces);
+            self.pieces_changed = false;
+        }
+        if self.ghost_changed {
+            update_mesh(ctx, &mut self.ghost_mesh, self.ghost.as_slice());
+            self.ghost_changed = false;
+        }
+    }
+
+    pub fn mesh(&self) -> Option<&PieceMesh> {
+        self.mesh.as_ref()
+    }
+
+    pub fn ghost_mesh(&self) -> Option<&PieceMesh> {
+        self.ghost.and(self.ghost_mesh.as_ref())
+    }
+}
+
+/// Meshes the pieces into `mesh`, updating the buffers in place when they fit.
+fn update_mesh(ctx: &mut dyn RenderingBackend, mesh: &mut Option<PieceMesh>, pieces: &[Piece]) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for piece in pieces {
+        piece.append_mesh(&mut vertices, &mut indices);
+    }
+
+    if let Some(piece_mesh) = mesh {
+        if vertices.len() <= piece_mesh.vertex_capacity
+            && indices.len() <= piece_mesh.index_capacity
+        {
+            if !indices.is_empty() {
+                ctx.buffer_update(piece_mesh.vertex_buffer, BufferSource::slice(&vertices));
+                ctx.buffer_update(piece_mesh.index_buffer, BufferSource::slice(&indices));
+            }
+            piece_mesh.index_count = indices.len() as i32;
+            return;
+        }
+    }
+
+    if let Some(piece_mesh) = mesh.take() {
+        ctx.delete_buffer(piece_mesh.vertex_buffer);
+        ctx.delete_buffer(piece_mesh.index_buffer);
+    }
+
+    // Room to grow, so placing a piece rarely needs new buffers
+    let vertex_capacity = (vertices.len() * 2).max(256);
+    let index_capacity = (indices.len() * 2).max(384);
+    let vertex_buffer = ctx.new_buffer(
+        BufferType::VertexBuffer,
+        BufferUsage::Stream,
+        BufferSource::empty::<PieceVertex>(vertex_capacity),
+    );
+    let index_buffer = ctx.new_buffer(
+        BufferType::IndexBuffer,
+        BufferUsage::Stream,
+        BufferSource::empty::<u32>(index_capacity),
+    );
+    if !indices.is_empty() {
+        ctx.buffer_update(vertex_buffer, BufferSource::slice(&vertices));
+        ctx.buffer_update(index_buffer, BufferSource::slice(&indices));
+    }
+    *mesh = Some(PieceMesh {
+        vertex_buffer,
+        index_buffer,
+        index_count: indices.len() as i32,
+        vertex_capacity,
+        index_capacity,
+    });
+}
+
+/// The piece the player is about to place.
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    pub kind: PieceKind,
+    pub material: PieceMaterial,
+    /// Quarter turns around the y axis.
+    pub rotation: u8,
+}
+
+impl Placement {
+    pub fn rotate(&mut self) {
+        self.rotation = (self.rotation + 1) % 4;
+    }
+
+    pub fn piece_at(&self, position: Vec3) -> Piece {
+        Piece {
+            kind: self.kind,
+            material: self.material,
+            position,
+            rotation: self.rotation,
+        }
+    }
+}
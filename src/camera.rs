@@ -1,5 +1,5 @@
+use crate::input::{Action, Input};
 use glam::Vec3;
-use miniquad::KeyCode;
 
 pub struct Camera {
     pub position: Vec3,
@@ -7,10 +7,9 @@ pub struct Camera {
     pub up: Vec3,
     pub yaw: f32,
     pub pitch: f32,
+    /// Flying speed in units per second.
     pub movement_speed: f32,
     pub mouse_sensitivity: f32,
-    pub last_mouse_pos: (f32, f32),
-    pub keys: [bool; 65535],
 }
 
 impl Camera {
@@ -21,28 +20,48 @@ impl Camera {
             up: Vec3::new(0.0, 1.0, 0.0),
             yaw: -90.0,
             pitch: 0.0,
-            movement_speed: 0.5,
+            movement_speed: 30.0,
             mouse_sensitivity: 0.3,
-            last_mouse_pos: (0.0, 0.0),
-            keys: [false; 65535],
         }
     }
 
-    pub fn process_input(&mut self) {
-        if self.keys[KeyCode::W as usize] {
-            self.position += self.front * self.movement_speed;
+    /// Flies the camera with the movement actions for `dt` seconds.
+    pub fn process_input(&mut self, input: &Input, dt: f32) {
+        let distance = self.movement_speed * dt;
+        if input.is_held(Action::MoveForward) {
+            self.position += self.front * distance;
         }
-        if self.keys[KeyCode::S as usize] {
-            self.position -= self.front * self.movement_speed;
+        if input.is_held(Action::MoveBackward) {
+            self.position -= self.front * distance;
         }
-        if self.keys[KeyCode::A as usize] {
-            self.position -= self.front.cross(self.up).normalize() * self.movement_speed;
+        if input.is_held(Action::MoveLeft) {
+            self.position -= self.front.cross(self.up).normalize() * distance;
         }
-        if self.keys[KeyCode::D as usize] {
-            self.position += self.front.cross(self.up).normalize() * self.movement_speed;
+        if input.is_held(Action::MoveRight) {
+            self.position += self.front.cross(self.up).normalize() * distance;
         }
     }
 
+    /// Horizontal direction the movement actions point in, for walking.
+    pub fn walk_direction(&self, input: &Input) -> Vec3 {
+        let forward = Vec3::new(self.front.x, 0.0, self.front.z).normalize_or_zero();
+        let right = forward.cross(self.up);
+        let mut direction = Vec3::ZERO;
+        if input.is_held(Action::MoveForward) {
+            direction += forward;
+        }
+        if input.is_held(Action::MoveBackward) {
+            direction -= forward;
+        }
+        if input.is_held(Action::MoveLeft) {
+            direction -= right;
+        }
+        if input.is_held(Action::MoveRight) {
+            direction += right;
+        }
+        direction
+    }
+
     pub fn process_mouse(&mut self, xoffset: f32, yoffset: f32) {
         let xoffset = xoffset * self.mouse_sensitivity;
         let yoffset = yoffset * self.mouse_sensitivity;
@@ -50,12 +69,7 @@ impl Camera {
         self.yaw += xoffset;
         self.pitch += yoffset;
 
-        if self.pitch > 89.0 {
-            self.pitch = 89.0;
-        }
-        if self.pitch < -89.0 {
-            self.pitch = -89.0;
-        }
+        self.pitch = self.pitch.clamp(-89.0, 89.0);
 
         let yaw_radians = self.yaw.to_radians();
         let pitch_radians = self.pitch.to_radians();
@@ -67,4 +81,4 @@ impl Camera {
         )
         .normalize();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,366 @@
+use crate::scalar_field::ScalarField;
+use crate::world_config::WorldGenConfig;
+use glam::{IVec3, Vec3};
+use noise::{NoiseFn, Perlin};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Worms start in cubic regions of this size, so every chunk can find the worms
+/// that reach into it without knowing about the rest of the world.
+const WORM_REGION_SIZE: i32 = 64;
+/// Distance between two carved spheres along a worm, relative to its radius.
+const WORM_STEP: f32 = 0.5;
+
+/// Density that carved samples are pulled towards, relative to the surface threshold.
+const AIR_DENSITY_BELOW_THRESHOLD: f32 = 1.0;
+
+#[derive(Clone, Debug)]
+pub struct CaveConfig {
+    pub enabled: bool,
+    /// Frequency of the noise that forms large caverns.
+    pub cavern_frequency: f64,
+    /// Noise value above which caverns are carved, in `-1..1`. Higher means fewer caverns.
+    pub cavern_threshold: f64,
+    /// Frequency of the two noise fields whose zero crossings form spaghetti tunnels.
+    pub spaghetti_frequency: f64,
+    /// Width of spaghetti tunnels, in noise units.
+    pub spaghetti_width: f64,
+    pub worms_per_region: u32,
+    pub worm_length: u32,
+    pub worm_radius: f64,
+    /// Chance, from 0 to 1, for a worm to ignore surface sealing and break out
+    /// into the open.
+    pub entrance_chance: f64,
+    /// How far below the surface, in density units, caves are allowed to reach
+    /// full size. Closer to the surface they are squeezed shut. Must be positive.
+    pub seal_depth: f64,
+}
+
+impl Default for CaveConfig {
+    fn default() -> CaveConfig {
+        CaveConfig {
+            enabled: true,
+            cavern_frequency: 0.008,
+            cavern_threshold: 0.55,
+            spaghetti_frequency: 0.02,
+            spaghetti_width: 0.06,
+            worms_per_region: 2,
+            worm_length: 64,
+            worm_radius: 3.5,
+            entrance_chance: 0.15,
+            seal_depth: 0.2,
+        }
+    }
+}
+
+/// A sphere carved by a cave worm.
+struct WormSphere {
+    center: Vec3,
+    radius: f32,
+    entrance: bool,
+}
+
+/// Carves caverns, spaghetti tunnels and worm tunnels out of a generated field.
+/// Carving is a pure function of world position and density, so padded samples
+/// shared between chunks end up identical.
+pub fn carve_caves(scalar_field: &mut ScalarField, config: &WorldGenConfig, threshold: f32) {
+    let caves = &config.caves;
+    if !caves.enabled {
+        return;
+    }
+
+    let cavern_noise = Perlin::new(config.seed.wrapping_add(303));
+    let spaghetti_noise = [
+        Perlin::new(config.seed.wrapping_add(404)),
+        Perlin::new(config.seed.wrapping_add(505)),
+    ];
+
+    // Worms are rasterised into a carve buffer first, as each one only touches
+    // a handful of samples
+    let mut worm_carve = vec![0.0f32; scalar_field.len()];
+    let mut worm_entrance = vec![false; scalar_field.len()];
+    for sphere in worm_spheres(scalar_field, config) {
+        rasterise_sphere(scalar_field, &sphere, &mut worm_carve, &mut worm_entrance);
+    }
+
+    let air_density = threshold - AIR_DENSITY_BELOW_THRESHOLD;
+    let seal_depth = caves.seal_depth as f32;
+
+    scalar_field.update_parallel(|index, world, density| {
+        if density <= air_density {
+            return density;
+        }
+        let p = world.as_dvec3();
+
+        let cavern = cavern_noise.get((p * caves.cavern_frequency).to_array());
+        let cavern_carve = ((cavern - caves.cavern_threshold) / 0.1).clamp(0.0, 1.0);
+
+        // Squash the tunnel noise vertically, so tunnels run mostly sideways
+        let spaghetti_point =
+            (p * caves.spaghetti_frequency * glam::DVec3::new(1.0, 2.0, 1.0)).to_array();
+        let spaghetti = spaghetti_noise[0]
+            .get(spaghetti_point)
+            .abs()
+            .max(spaghetti_noise[1].get(spaghetti_point).abs());
+        let spaghetti_carve = (1.0 - spaghetti / caves.spaghetti_width).clamp(0.0, 1.0);
+
+        let noise_carve = cavern_carve.max(spaghetti_carve) as f32;
+
+        // Near the surface the density is close to the threshold, so scaling the
+        // carving by depth keeps caves from opening up to the sky
+        let depth = ((density - threshold) / seal_depth).clamp(0.0, 1.0);
+        let seal = depth * depth * (3.0 - 2.0 * depth);
+
+        let worm = worm_carve[index];
+        let sealed_carve = if worm_entrance[index] {
+            noise_carve
+        } else {
+            noise_carve.max(worm)
+        };
+        let mut carved = density + (air_density - density) * sealed_carve * seal;
+        // Scaling alone still lets a thin crack open right under the surface, so
+        // sealed caves keep a solid shell until full depth
+        if depth < 1.0 {
+            carved = carved.max(threshold.min(density));
+        }
+        if worm_entrance[index] {
+            carved = carved.min(density + (air_density - density) * worm);
+        }
+        carved
+    });
+}
+
+/// Spheres of every worm that can reach into the field.
+fn worm_spheres(scalar_field: &ScalarField, config: &WorldGenConfig) -> Vec<WormSphere> {
+    let caves = &config.caves;
+    let reach = (caves.worm_length as f32 * caves.worm_radius as f32 * WORM_STEP
+        + caves.worm_radius as f32) as i32;
+
+    let field_min = scalar_field.origin();
+    let dimensions = scalar_field.dimensions();
+    let field_max = field_min
+        + IVec3::new(
+            dimensions[0] as i32,
+            dimensions[1] as i32,
+            dimensions[2] as i32,
+        ) * scalar_field.step();
+    let region_min = (field_min - reach).div_euclid(IVec3::splat(WORM_REGION_SIZE));
+    let region_max = (field_max + reach).div_euclid(IVec3::splat(WORM_REGION_SIZE));
+
+    let mut spheres = Vec::new();
+    for x in region_min.x..=region_max.x {
+        for y in region_min.y..=region_max.y {
+            for z in region_min.z..=region_max.z {
+                let region = IVec3::new(x, y, z);
+                let mut rng = StdRng::seed_from_u64(region_seed(config.seed, region));
+                for _ in 0..caves.worms_per_region {
+                    let start = (region * WORM_REGION_SIZE).as_vec3()
+                        + Vec3::new(rng.gen(), rng.gen(), rng.gen()) * WORM_REGION_SIZE as f32;
+                    let entrance = rng.gen_bool(caves.entrance_chance);
+
+                    let mut position = start;
+                    let mut yaw: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+                    let mut pitch: f32 = rng.gen_range(-0.3..0.3);
+                    for _ in 0..caves.worm_length {
+                        let radius = caves.worm_radius as f32 * rng.gen_range(0.7..1.3);
+                        let sphere_min = (position - radius).floor().as_ivec3();
+                        let sphere_max = (position + radius).ceil().as_ivec3();
+                        if sphere_max.cmpge(field_min).all() && sphere_min.cmplt(field_max).all() {
+                            spheres.push(WormSphere {
+                                center: position,
+                                radius,
+                                entrance,
+                            });
+                        }
+
+                        yaw += rng.gen_range(-0.4..0.4);
+                        pitch = (pitch + rng.gen_range(-0.2..0.2)).clamp(-0.8, 0.8);
+                        let direction = Vec3::new(
+                            yaw.cos() * pitch.cos(),
+                            pitch.sin(),
+                            yaw.sin() * pitch.cos(),
+                        );
+                        position += direction * radius * WORM_STEP;
+                    }
+                }
+            }
+        }
+    }
+    spheres
+}
+
+fn rasterise_sphere(
+    scalar_field: &ScalarField,
+    sphere: &WormSphere,
+    carve: &mut [f32],
+    entrance: &mut [bool],
+) {
+    let origin = scalar_field.origin().as_vec3();
+    let step = scalar_field.step() as f32;
+    let dimensions = scalar_field.dimensions();
+    let min = ((sphere.center - sphere.radius - origin) / step)
+        .floor()
+        .as_ivec3()
+        .max(IVec3::ZERO);
+    let max = ((sphere.center + sphere.radius - origin) / step)
+        .ceil()
+        .as_ivec3()
+        .min(IVec3::new(
+            dimensions[0] as i32 - 1,
+            dimensions[1] as i32 - 1,
+            dimensions[2] as i32 - 1,
+        ));
+
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                let world = scalar_field
+                    .world_position([x as usize, y as usize, z as usize])
+                    .as_vec3();
+                let distance = world.distance(sphere.center);
+                // Full strength in the inner half, fading out towards the rim
+                let amount = ((1.0 - distance / sphere.radius) * 2.0).clamp(0.0, 1.0);
+                if amount > 0.0 {
+                    let index = scalar_field.index(x as usize, y as usize, z as usize);
+                    carve[index] = carve[index].max(amount);
+                    entrance[index] |= sphere.entrance;
+                }
+            }
+        }
+    }
+}
+
+fn region_seed(world_seed: u32, region: IVec3) -> u64 {
+    (world_seed as u64)
+        ^ (region.x as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (region.y as i64 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (region.z as i64 as u64).wrapping_mul(0x1656_67B1_9E37_79F9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar_generator::generate_scalar_field;
+    use std::sync::OnceLock;
+
+    const THRESHOLD: f32 = 0.9;
+    const SIZE: [usize; 3] = [40, 64, 40];
+    /// Reaches from deep underground up into the sky of the default world.
+    const ORIGIN: IVec3 = IVec3::new(0, 40, 0);
+
+    /// Field before and after carving, as air masks. Cave settings don't change
+    /// the uncarved field, so it is only generated once.
+    fn carved(config: &WorldGenConfig) -> (Vec<bool>, Vec<bool>, ScalarField) {
+        static UNCARVED: OnceLock<ScalarField> = OnceLock::new();
+        let mut field = UNCARVED
+            .get_or_init(|| generate_scalar_field(&WorldGenConfig::default(), SIZE, ORIGIN, 1))
+            .clone();
+        let before = air(&field);
+        carve_caves(&mut field, config, THRESHOLD);
+        (before, air(&field), field)
+    }
+
+    fn air(field: &ScalarField) -> Vec<bool> {
+        (0..field.len())
+            .map(|index| {
+                let [x, y, z] = field.sample_position(index);
+                field.get(x, y, z) < THRESHOLD
+            })
+            .collect()
+    }
+
+    fn neighbours(field: &ScalarField, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let position = field.sample_position(index);
+        let dimensions = field.dimensions();
+        (0..6).filter_map(move |direction| {
+            let mut neighbour = position;
+            let axis = direction / 2;
+            if direction % 2 == 0 {
+                neighbour[axis] = neighbour[axis].checked_sub(1)?;
+            } else if neighbour[axis] + 1 < dimensions[axis] {
+                neighbour[axis] += 1;
+            } else {
+                return None;
+            }
+            Some(field.index(neighbour[0], neighbour[1], neighbour[2]))
+        })
+    }
+
+    fn on_border(field: &ScalarField, index: usize) -> bool {
+        let position = field.sample_position(index);
+        (0..3).any(|axis| position[axis] == 0 || position[axis] + 1 == field.dimensions()[axis])
+    }
+
+    /// Air samples connected to `seeds` through air.
+    fn flood_fill(
+        field: &ScalarField,
+        air: &[bool],
+        seeds: impl Iterator<Item = usize>,
+    ) -> Vec<bool> {
+        let mut reached = vec![false; air.len()];
+        let mut stack: Vec<usize> = seeds.filter(|&index| air[index]).collect();
+        for &index in &stack {
+            reached[index] = true;
+        }
+        while let Some(index) = stack.pop() {
+            for neighbour in neighbours(field, index) {
+                if air[neighbour] && !reached[neighbour] {
+                    reached[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Connected air volumes that neither touch the border of the field nor
+    /// connect to air that was there before carving.
+    fn enclosed_air_volumes(field: &ScalarField, before: &[bool], after: &[bool]) -> usize {
+        let mut visited = vec![false; after.len()];
+        let mut volumes = 0;
+        for start in 0..after.len() {
+            if !after[start] || visited[start] {
+                continue;
+            }
+            let component = flood_fill(field, after, std::iter::once(start));
+            let mut enclosed = true;
+            for index in (0..after.len()).filter(|&index| component[index]) {
+                visited[index] = true;
+                enclosed &= !before[index] && !on_border(field, index);
+            }
+            if enclosed {
+                volumes += 1;
+            }
+        }
+        volumes
+    }
+
+    #[test]
+    fn known_seed_carves_enclosed_caves() {
+        let (before, after, field) = carved(&WorldGenConfig::default());
+
+        assert!(before.contains(&true) && before.contains(&false));
+        assert_eq!(enclosed_air_volumes(&field, &before, &before), 0);
+        assert_eq!(enclosed_air_volumes(&field, &before, &after), 5);
+    }
+
+    #[test]
+    fn sealed_worms_stay_below_the_surface() {
+        let mut config = WorldGenConfig::default();
+        // Worms only, none of them allowed to open up
+        config.caves.cavern_threshold = 2.0;
+        config.caves.spaghetti_width = 1e-9;
+        config.caves.worms_per_region = 8;
+        config.caves.entrance_chance = 0.0;
+        let (before, after, field) = carved(&config);
+
+        let carved_samples = (0..after.len())
+            .filter(|&index| after[index] && !before[index])
+            .count();
+        assert!(carved_samples > 0, "worms should carve something");
+
+        // Flooding from the open air never reaches a carved sample
+        let open_air = flood_fill(&field, &after, (0..before.len()).filter(|&i| before[i]));
+        assert!((0..after.len()).all(|index| !open_air[index] || before[index]));
+    }
+}
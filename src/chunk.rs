@@ -0,0 +1,570 @@
+use crate::aabb::Aabb;
+use crate::brush::BrushStroke;
+use crate::caves::carve_caves;
+use crate::extras::Vertex;
+use crate::lod::ChunkLod;
+use crate::marching_cubes::{generate_marching_cubes_skipping, FIELD_PADDING};
+use crate::material::{paint_materials, Material};
+use crate::raycast::{raycast, IsoSurface, RayHit};
+use crate::scalar_field::ScalarField;
+use crate::scalar_generator::generate_scalar_field;
+use crate::transition::{TransitionCells, TransitionFields};
+use crate::world_config::WorldGenConfig;
+use glam::{IVec3, Vec3};
+use miniquad::*;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+/// Number of cells along each axis of a chunk.
+pub const CHUNK_SIZE: usize = 32;
+
+/// Chunks loaded in every direction around the camera on the XZ plane. Distant
+/// chunks are meshed at a coarser level of detail.
+const VIEW_DISTANCE: i32 = 12;
+/// Chunks loaded above and below the camera.
+const VERTICAL_VIEW_DISTANCE: i32 = 3;
+/// Maximum number of chunks being generated on worker threads at once. Keeping
+/// the queue short lets newly requested chunks near the camera go first.
+const MAX_PENDING_CHUNKS: usize = 16;
+
+/// Density of the terrain surface. Denser samples are solid.
+pub const THRESHOLD: f32 = 0.9;
+
+pub struct Chunk {
+    pub vertex_buffer: BufferId,
+    pub index_buffer: BufferId,
+    pub index_count: i32,
+    /// Bounds of the mesh, for culling.
+    pub bounds: Aabb,
+    /// Vertices and indices the buffers can hold. Edited chunks are re-uploaded
+    /// in place while the new mesh still fits.
+    vertex_capacity: usize,
+    index_capacity: usize,
+}
+
+/// A generated chunk. The field is kept for editing; `mesh` is `None` when the
+/// chunk has no surface in it.
+struct ReadyChunk {
+    /// Shared with a worker thread while the chunk is being remeshed there.
+    field: Arc<ScalarField>,
+    /// Samples along the borders with finer chunks, for the current `lod`.
+    transition: TransitionFields,
+    mesh: Option<Chunk>,
+    lod: ChunkLod,
+}
+
+#[derive(Default)]
+struct ChunkSlot {
+    ready: Option<ReadyChunk>,
+    /// Level of detail the chunk is being generated or remeshed at on a worker
+    /// thread. Results for any other level are stale and dropped.
+    pending: Option<ChunkLod>,
+}
+
+/// Chunk built on a worker thread, waiting to be uploaded to the GPU.
+struct ChunkMesh {
+    coord: IVec3,
+    lod: ChunkLod,
+    field: Arc<ScalarField>,
+    transition: TransitionFields,
+    /// Whether an existing field was only remeshed, rather than generated.
+    remeshed: bool,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+pub struct ChunkManager {
+    config: Arc<WorldGenConfig>,
+    chunks: HashMap<IVec3, ChunkSlot>,
+    pending_count: usize,
+    /// Every terrain edit so far, replayed on chunks generated after it.
+    strokes: Vec<BrushStroke>,
+    sender: Sender<ChunkMesh>,
+    receiver: Receiver<ChunkMesh>,
+}
+
+impl ChunkManager {
+    pub fn new(config: WorldGenConfig) -> ChunkManager {
+        let (sender, receiver) = channel();
+        ChunkManager {
+            config: Arc::new(config),
+            chunks: HashMap::new(),
+            pending_count: 0,
+            strokes: Vec::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Queues chunks around `center` whose level of detail is missing or out of
+    /// date, nearest first, and unloads the ones that are out of range.
+    ///
+    /// Chunks that only need new transition cells are remeshed from their field;
+    /// chunks changing resolution are generated again. Either way the old mesh stays
+    /// visible until the new one is uploaded.
+    pub fn update(&mut self, ctx: &mut dyn RenderingBackend, center: Vec3) {
+        let center_coord = world_to_chunk(center);
+
+        let out_of_range: Vec<IVec3> = self
+            .chunks
+            .keys()
+            .filter(|coord| !in_range(**coord, center_coord))
+            .copied()
+            .collect();
+        for coord in out_of_range {
+            if let Some(ChunkSlot {
+                ready:
+                    Some(ReadyChunk {
+                        mesh: Some(chunk), ..
+                    }),
+                ..
+            }) = self.chunks.remove(&coord)
+            {
+                ctx.delete_buffer(chunk.vertex_buffer);
+                ctx.delete_buffer(chunk.index_buffer);
+            }
+        }
+
+        let mut outdated = Vec::new();
+        for x in -VIEW_DISTANCE..=VIEW_DISTANCE {
+            for y in -VERTICAL_VIEW_DISTANCE..=VERTICAL_VIEW_DISTANCE {
+                for z in -VIEW_DISTANCE..=VIEW_DISTANCE {
+                    let coord = center_coord + IVec3::new(x, y, z);
+                    let lod = ChunkLod::new(coord, center_coord);
+                    let up_to_date = self.chunks.get(&coord).is_some_and(|slot| {
+                        slot.pending == Some(lod)
+                            || (slot.pending.is_none()
+                                && slot.ready.as_ref().is_some_and(|ready| ready.lod == lod))
+                    });
+                    if !up_to_date {
+                        outdated.push((coord, lod));
+                    }
+                }
+            }
+        }
+        // Missing chunks first, then the nearest
+        outdated.sort_by_key(|(coord, _)| {
+            (
+                self.chunks.contains_key(coord),
+                (*coord - center_coord).length_squared(),
+            )
+        });
+
+        let free_slots = MAX_PENDING_CHUNKS.saturating_sub(self.pending_count);
+        for (coord, lod) in outdated.into_iter().take(free_slots) {
+            let slot = self.chunks.entry(coord).or_default();
+            slot.pending = Some(lod);
+            self.pending_count += 1;
+
+            // Keep the field when only the transition cells change
+            let field = slot
+                .ready
+                .as_ref()
+                .filter(|ready| ready.lod.step == lod.step)
+                .map(|ready| ready.field.clone());
+
+            let config = self.config.clone();
+            let sender = self.sender.clone();
+            rayon::spawn(move || {
+                let remeshed = field.is_some();
+                let field =
+                    field.unwrap_or_else(|| Arc::new(build_chunk_field(&config, coord, lod.step)));
+                let transition = TransitionFields::new(coord, &lod, |dimensions, origin, step| {
+                    build_field(&config, dimensions, origin, step)
+                });
+                let (vertices, indices) = mesh_chunk(&field, &transition, coord, &lod);
+                // The manager may have been dropped on shutdown
+                let _ = sender.send(ChunkMesh {
+                    coord,
+                    lod,
+                    field,
+                    transition,
+                    remeshed,
+                    vertices,
+                    indices,
+                });
+            });
+        }
+    }
+
+    /// Uploads meshes finished by the worker threads since the last call.
+    pub fn upload_finished(&mut self, ctx: &mut dyn RenderingBackend) {
+        while let Ok(mut mesh) = self.receiver.try_recv() {
+            self.pending_count -= 1;
+
+            // Chunks that went out of range or changed level of detail while
+            // generating are dropped here
+            let Some(slot) = self.chunks.get_mut(&mesh.coord) else {
+                continue;
+            };
+            if slot.pending != Some(mesh.lod) {
+                continue;
+            }
+            slot.pending = None;
+
+            // An edit while remeshing gave the chunk a new field, and the mesh of
+            // the old one is out of date. The next update queues it again.
+            if mesh.remeshed {
+                let current = slot.ready.as_ref().map(|ready| &ready.field);
+                if !current.is_some_and(|field| Arc::ptr_eq(field, &mesh.field)) {
+                    continue;
+                }
+            }
+
+            // Edits made before or while the chunk was generating. A remeshed
+            // field has them already, but its transition fields are new.
+            let mut edited = false;
+            for stroke in &self.strokes {
+                if !mesh.remeshed && stroke.touches(&mesh.field) {
+                    stroke.apply(Arc::make_mut(&mut mesh.field), THRESHOLD);
+                    edited = true;
+                }
+                edited |= mesh.transition.apply(stroke, THRESHOLD);
+            }
+            if edited {
+                (mesh.vertices, mesh.indices) =
+                    mesh_chunk(&mesh.field, &mesh.transition, mesh.coord, &mesh.lod);
+            }
+
+            let ready = slot.ready.get_or_insert(ReadyChunk {
+                field: mesh.field.clone(),
+                transition: TransitionFields::default(),
+                mesh: None,
+                lod: mesh.lod,
+            });
+            ready.field = mesh.field;
+            ready.transition = mesh.transition;
+            ready.lod = mesh.lod;
+            update_chunk_mesh(ctx, &mut ready.mesh, &mesh.vertices, &mesh.indices);
+        }
+    }
+
+    /// Applies a brush stroke to every loaded chunk it reaches and remeshes those
+    /// chunks. The stroke is also kept for chunks that load later.
+    pub fn apply_brush(&mut self, ctx: &mut dyn RenderingBackend, mut stroke: BrushStroke) {
+        stroke.sample_smoothing(self);
+        for (coord, slot) in &mut self.chunks {
+            let Some(ready) = &mut slot.ready else {
+                continue;
+            };
+            let mut touched = ready.transition.apply(&stroke, THRESHOLD);
+            if stroke.touches(&ready.field) {
+                stroke.apply(Arc::make_mut(&mut ready.field), THRESHOLD);
+                touched = true;
+            }
+            if !touched {
+                continue;
+            }
+            let (vertices, indices) =
+                mesh_chunk(&ready.field, &ready.transition, *coord, &ready.lod);
+            update_chunk_mesh(ctx, &mut ready.mesh, &vertices, &indices);
+        }
+        self.strokes.push(stroke);
+    }
+
+    /// Whether the terrain can hold up a building at a world position. Terrain
+    /// that isn't loaded counts as solid, so buildings out of range or not
+    /// streamed in yet don't collapse.
+    pub fn holds_up(&self, position: Vec3) -> bool {
+        self.density(position)
+            .is_none_or(|density| density >= THRESHOLD)
+    }
+
+    /// First terrain surface hit by a ray, within `max_distance`.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        raycast(self, origin, direction, max_distance, THRESHOLD)
+    }
+
+    /// Field of the generated chunk containing a world position. Every position
+    /// in the chunk lies at least `FIELD_PADDING` samples inside the field.
+    fn field_at(&self, position: Vec3) -> Option<&ScalarField> {
+        let ready = self.chunks.get(&world_to_chunk(position))?.ready.as_ref()?;
+        Some(&ready.field)
+    }
+
+    pub fn loaded_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks
+            .values()
+            .filter_map(|slot| slot.ready.as_ref()?.mesh.as_ref())
+    }
+}
+
+impl IsoSurface for ChunkManager {
+    /// Density interpolated from the loaded chunk fields. `None` when the chunk
+    /// containing the position isn't loaded yet.
+    fn density(&self, position: Vec3) -> Option<f32> {
+        let field = self.field_at(position)?;
+
+        let local = (position - field.origin().as_vec3()) / field.step() as f32;
+        let base = local.floor();
+        let t = local - base;
+        let [x, y, z] = [base.x as usize, base.y as usize, base.z as usize];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let plane = |dx: usize| {
+            let line = |dy: usize| {
+                lerp(
+                    field.get(x + dx, y + dy, z),
+                    field.get(x + dx, y + dy, z + 1),
+                    t.z,
+                )
+            };
+            lerp(line(0), line(1), t.y)
+        };
+        Some(lerp(plane(0), plane(1), t.x))
+    }
+
+    /// Material of the nearest field sample.
+    fn material(&self, position: Vec3) -> Option<Material> {
+        let field = self.field_at(position)?;
+        let local = ((position - field.origin().as_vec3()) / field.step() as f32).round();
+        Some(field.material(local.x as usize, local.y as usize, local.z as usize))
+    }
+}
+
+/// Generates the scalar field of a single chunk with `step` world units between
+/// samples. Runs on a worker thread.
+fn build_chunk_field(config: &WorldGenConfig, coord: IVec3, step: i32) -> ScalarField {
+    // The field reaches one sample into the next chunk plus the padding on both
+    // sides, so border cells see the same samples as their neighbours
+    let grid_size = CHUNK_SIZE / step as usize + 1 + 2 * FIELD_PADDING;
+    let origin = coord * CHUNK_SIZE as i32 - IVec3::splat(FIELD_PADDING as i32 * step);
+    build_field(config, [grid_size; 3], origin, step)
+}
+
+/// Generates, paints and carves a field. Each sample only depends on its world
+/// position, so fields overlapping at the same step agree where they overlap.
+fn build_field(
+    config: &WorldGenConfig,
+    dimensions: [usize; 3],
+    origin: IVec3,
+    step: i32,
+) -> ScalarField {
+    let mut scalar_field = generate_scalar_field(config, dimensions, origin, step);
+    paint_materials(&mut scalar_field, config, THRESHOLD);
+    carve_caves(&mut scalar_field, config, THRESHOLD);
+    scalar_field
+}
+
+/// Meshes a chunk's field at its level of detail, with transition cells along
+/// the borders with finer chunks.
+fn mesh_chunk(
+    field: &ScalarField,
+    transition: &TransitionFields,
+    coord: IVec3,
+    lod: &ChunkLod,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let cells = CHUNK_SIZE / lod.step as usize;
+    let transition_cells = TransitionCells::new(field, transition, coord, *lod, THRESHOLD);
+    let (mut vertices, mut indices) =
+        generate_marching_cubes_skipping(field, cells, THRESHOLD, |cell| {
+            transition_cells.contains(cell)
+        });
+    transition_cells.mesh(&mut vertices, &mut indices);
+    (vertices, indices)
+}
+
+/// Puts a new mesh on a generated chunk. The existing buffers are updated in
+/// place when the mesh fits, and replaced otherwise. Empty air and solid ground
+/// don't keep any GPU buffers.
+fn update_chunk_mesh(
+    ctx: &mut dyn RenderingBackend,
+    mesh: &mut Option<Chunk>,
+    vertices: &[Vertex],
+    indices: &[u32],
+) {
+    if let Some(chunk) = mesh {
+        if !indices.is_empty()
+            && vertices.len() <= chunk.vertex_capacity
+            && indices.len() <= chunk.index_capacity
+        {
+            ctx.buffer_update(chunk.vertex_buffer, BufferSource::slice(vertices));
+            ctx.buffer_update(chunk.index_buffer, BufferSource::slice(indices));
+            chunk.index_count = indices.len() as i32;
+            chunk.bounds = mesh_bounds(vertices);
+            return;
+        }
+    }
+
+    if let Some(chunk) = mesh.take() {
+        ctx.delete_buffer(chunk.vertex_buffer);
+        ctx.delete_buffer(chunk.index_buffer);
+    }
+    if !indices.is_empty() {
+        *mesh = Some(upload_chunk(ctx, vertices, indices));
+    }
+}
+
+fn upload_chunk(ctx: &mut dyn RenderingBackend, vertices: &[Vertex], indices: &[u32]) -> Chunk {
+    // Streamed buffers with some headroom, so edits rarely need new ones
+    let vertex_capacity = vertices.len() + vertices.len() / 2;
+    let index_capacity = indices.len() + indices.len() / 2;
+
+    let vertex_buffer = ctx.new_buffer(
+        BufferType::VertexBuffer,
+        BufferUsage::Stream,
+        BufferSource::empty::<Vertex>(vertex_capacity),
+    );
+    ctx.buffer_update(vertex_buffer, BufferSource::slice(vertices));
+
+    let index_buffer = ctx.new_buffer(
+        BufferType::IndexBuffer,
+        BufferUsage::Stream,
+        BufferSource::empty::<u32>(index_capacity),
+    );
+    ctx.buffer_update(index_buffer, BufferSource::slice(indices));
+
+    Chunk {
+        vertex_buffer,
+        index_buffer,
+        index_count: indices.len() as i32,
+        bounds: mesh_bounds(vertices),
+        vertex_capacity,
+        index_capacity,
+    }
+}
+
+fn mesh_bounds(vertices: &[Vertex]) -> Aabb {
+    Aabb::from_points(vertices.iter().map(|vertex| Vec3::from(vertex.pos)))
+}
+
+pub fn world_to_chunk(position: Vec3) -> IVec3 {
+    (position / CHUNK_SIZE as f32).floor().as_ivec3()
+}
+
+fn in_range(coord: IVec3, center: IVec3) -> bool {
+    let offset = (coord - center).abs();
+    offset.x <= VIEW_DISTANCE && offset.z <= VIEW_DISTANCE && offset.y <= VERTICAL_VIEW_DISTANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    /// Row of chunks the surface of the default world runs through.
+    const GROUND: i32 = 3;
+
+    type EdgeBits = [[u32; 3]; 2];
+
+    /// Mesh of the chunk at `coord` with the camera in chunk `center`.
+    fn chunk_mesh(coord: IVec3, center: IVec3) -> (Vec<Vertex>, Vec<u32>) {
+        let config = WorldGenConfig::default();
+        let lod = ChunkLod::new(coord, center);
+        let field = build_chunk_field(&config, coord, lod.step);
+        let transition = TransitionFields::new(coord, &lod, |dimensions, origin, step| {
+            build_field(&config, dimensions, origin, step)
+        });
+        mesh_chunk(&field, &transition, coord, &lod)
+    }
+
+    fn position_bits(vertex: &Vertex) -> [u32; 3] {
+        vertex.pos.map(f32::to_bits)
+    }
+
+    /// Edges on the border of a mesh, used by a single triangle, by the exact
+    /// positions of their ends.
+    fn open_edges((vertices, indices): &(Vec<Vertex>, Vec<u32>)) -> Vec<(Vec3, Vec3)> {
+        let mut uses: BTreeMap<EdgeBits, (Vec3, Vec3, usize)> = BTreeMap::new();
+        for triangle in indices.chunks(3) {
+            for k in 0..3 {
+                let a = &vertices[triangle[k] as usize];
+                let b = &vertices[triangle[(k + 1) % 3] as usize];
+                let (a, b) = if position_bits(a) <= position_bits(b) {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                let key = [position_bits(a), position_bits(b)];
+                let entry = uses
+                    .entry(key)
+                    .or_insert((Vec3::from(a.pos), Vec3::from(b.pos), 0));
+                entry.2 += 1;
+            }
+        }
+        uses.into_values()
+            .filter(|&(_, _, count)| count == 1)
+            .map(|(a, b, _)| (a, b))
+            .collect()
+    }
+
+    fn edge_bits((a, b): (Vec3, Vec3)) -> EdgeBits {
+        [
+            a.to_array().map(f32::to_bits),
+            b.to_array().map(f32::to_bits),
+        ]
+    }
+
+    #[test]
+    fn transition_cells_match_a_finer_face_neighbour() {
+        let center = IVec3::new(-1, GROUND, 0);
+        let fine_coord = IVec3::new(0, GROUND, 0);
+        let coarse_coord = IVec3::new(1, GROUND, 0);
+        assert_eq!(ChunkLod::new(fine_coord, center).step, 1);
+        assert_eq!(ChunkLod::new(coarse_coord, center).step, 2);
+        let fine = chunk_mesh(fine_coord, center);
+        let coarse = chunk_mesh(coarse_coord, center);
+
+        let face = CHUNK_SIZE as f32;
+        let seam = |mesh: &(Vec<Vertex>, Vec<u32>)| -> BTreeSet<EdgeBits> {
+            open_edges(mesh)
+                .into_iter()
+                .filter(|(a, b)| a.x == face && b.x == face)
+                .map(edge_bits)
+                .collect()
+        };
+        let fine_seam = seam(&fine);
+        assert!(!fine_seam.is_empty(), "the surface should cross the face");
+        assert_eq!(fine_seam, seam(&coarse));
+
+        // Normals along the seam agree too, so the shading doesn't jump
+        let seam_vertices = |(vertices, _): &(Vec<Vertex>, Vec<u32>)| -> BTreeSet<[u32; 6]> {
+            vertices
+                .iter()
+                .filter(|vertex| vertex.pos[0] == face)
+                .map(|vertex| {
+                    let [x, y, z] = position_bits(vertex);
+                    let [nx, ny, nz] = vertex.normal.map(f32::to_bits);
+                    [x, y, z, nx, ny, nz]
+                })
+                .collect()
+        };
+        assert_eq!(seam_vertices(&fine), seam_vertices(&coarse));
+    }
+
+    #[test]
+    fn band_corners_close_where_chunks_only_share_an_edge() {
+        // The step 1 chunk at (1, 1) touches the step 2 chunk at (2, 2) only
+        // along an edge, with step 2 chunks on both of its other sides
+        let center = IVec3::new(0, GROUND, 0);
+        let coords = [(1, 1), (2, 1), (1, 2), (2, 2)].map(|(x, z)| IVec3::new(x, GROUND, z));
+        assert_eq!(ChunkLod::new(coords[0], center).step, 1);
+        assert!(coords[1..]
+            .iter()
+            .all(|&coord| ChunkLod::new(coord, center).step == 2));
+
+        let size = CHUNK_SIZE as f32;
+        let block_min = (coords[0] * CHUNK_SIZE as i32).as_vec3();
+        let block_max = block_min + Vec3::new(2.0 * size, size, 2.0 * size);
+        let middle = block_min + size;
+
+        // Every open edge on the faces between the four chunks has to be
+        // matched by an open edge of the chunk across
+        let mut seam: BTreeMap<EdgeBits, usize> = BTreeMap::new();
+        for coord in coords {
+            for (a, b) in open_edges(&chunk_mesh(coord, center)) {
+                let midpoint = (a + b) / 2.0;
+                let inside = midpoint.cmpgt(block_min).all() && midpoint.cmplt(block_max).all();
+                let on_x = a.x == middle.x && b.x == middle.x;
+                let on_z = a.z == middle.z && b.z == middle.z;
+                if inside && on_x != on_z {
+                    *seam.entry(edge_bits((a, b))).or_default() += 1;
+                }
+            }
+        }
+
+        assert!(!seam.is_empty(), "the surface should cross the seams");
+        let unmatched = seam.values().filter(|&&count| count != 2).count();
+        assert_eq!(unmatched, 0, "{unmatched} of {} seam edges", seam.len());
+    }
+}
@@ -305,4 +305,93 @@ pub const TRIANGULATION_TABLE: [[i8; 16]; 256] = [
 	[1, 9, 0, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
 	[8, 3, 0, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
 	[-1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
-];
\ No newline at end of file
+];
+
+/// Corners of each cube face, counter-clockwise seen from outside the cube. Faces
+/// are ordered -x, +x, -y, +y, -z, +z.
+pub const CUBE_FACES: [[usize; 4]; 6] = [
+	[0, 4, 6, 2],
+	[1, 3, 7, 5],
+	[0, 1, 5, 4],
+	[2, 6, 7, 3],
+	[0, 2, 3, 1],
+	[4, 5, 7, 6],
+];
+
+/// For every cube index, the faces in `CUBE_FACES` order where the triangulation
+/// above joins two diagonally opposite solid corners, cutting off the air
+/// corners instead. Only set on ambiguous faces, with two solid and two air
+/// corners on opposite diagonals; the table is not consistent about these, so
+/// cells meshed another way look up how their neighbour resolved a shared face.
+/// Derived from `TRIANGULATION_TABLE`.
+pub const SOLID_JOINING_FACES: [u8; 256] = [
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x00, 0x04, 0x00, 0x01, 0x00, 0x15, 0x00, 0x00, 0x10, 0x04, 0x00, 0x01, 0x00, 0x05, 0x00,
+	0x00, 0x04, 0x00, 0x00, 0x00, 0x04, 0x10, 0x00, 0x02, 0x16, 0x00, 0x00, 0x02, 0x06, 0x00, 0x00,
+	0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x11, 0x00, 0x02, 0x12, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+	0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x10, 0x00, 0x08, 0x19, 0x08, 0x09, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x14, 0x00, 0x08, 0x18, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x20, 0x25, 0x20, 0x21, 0x20, 0x24, 0x30, 0x00, 0x2a, 0x3f, 0x28, 0x00, 0x22, 0x00, 0x00, 0x00,
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x00, 0x02, 0x02, 0x08, 0x08, 0x1a, 0x0a, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x20, 0x20, 0x26, 0x22, 0x29, 0x28, 0x3f, 0x00, 0x20, 0x30, 0x24, 0x00, 0x21, 0x00, 0x00, 0x00,
+	0x00, 0x04, 0x00, 0x00, 0x08, 0x0c, 0x18, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x01, 0x02, 0x03, 0x00, 0x00, 0x12, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Recomputes `SOLID_JOINING_FACES` from the triangulation: on an ambiguous
+	/// face, the outline of the surface cuts off the corners on one diagonal,
+	/// and the solid corners are joined when those are the air corners.
+	fn solid_joining_faces(cube_index: usize) -> u8 {
+		let triangles: Vec<[usize; 3]> = TRIANGULATION_TABLE[cube_index]
+			.chunks(3)
+			.take_while(|triangle| triangle[0] != -1)
+			.map(|triangle| [0, 1, 2].map(|k| triangle[k] as usize))
+			.collect();
+		// Sides used by a single triangle lie on the cube faces
+		let sides: Vec<[usize; 2]> = triangles
+			.iter()
+			.flat_map(|&[a, b, c]| [[a, b], [b, c], [c, a]])
+			.map(|[a, b]| [a.min(b), a.max(b)])
+			.collect();
+		let outline = sides
+			.iter()
+			.filter(|side| sides.iter().filter(|other| other == side).count() == 1);
+
+		let is_air = |corner: usize| cube_index & (1 << corner) != 0;
+		let mut faces = 0;
+		for (face, corners) in CUBE_FACES.iter().enumerate() {
+			let [a, b, c, d] = corners.map(is_air);
+			if a != c || b != d || a == b {
+				continue;
+			}
+			let on_face = |edge: usize| EDGE_VERTEX_INDICES[edge].iter().all(|corner| corners.contains(corner));
+			for &[first, second] in outline.clone() {
+				if !on_face(first) || !on_face(second) {
+					continue;
+				}
+				let [p, q] = EDGE_VERTEX_INDICES[first];
+				let cut_off = if EDGE_VERTEX_INDICES[second].contains(&p) { p } else { q };
+				if is_air(cut_off) {
+					faces |= 1 << face;
+				}
+			}
+		}
+		faces
+	}
+
+	#[test]
+	fn solid_joining_faces_match_the_triangulation() {
+		for (cube_index, &faces) in SOLID_JOINING_FACES.iter().enumerate() {
+			assert_eq!(faces, solid_joining_faces(cube_index), "cube index {}", cube_index);
+		}
+	}
+}
\ No newline at end of file
@@ -0,0 +1,618 @@
+use crate::biome::{Biome, ClimateMap, BIOME_COUNT, MIN_BLEND_WEIGHT};
+use glam::DVec3;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti, Simplex};
+use serde_json::{json, Map, Value};
+
+/// Compiled density function, evaluated at a world position.
+pub type DensitySampler = Box<dyn Fn(DVec3) -> f64 + Send + Sync>;
+
+/// A tree of density operations describing the terrain shape. Positive values
+/// are solid, and the surface sits where the density crosses the mesher threshold.
+///
+/// Trees are plain data so they can be loaded from and saved to JSON, and are
+/// turned into a [`DensitySampler`] with [`DensityFunction::compile`].
+#[derive(Clone, Debug)]
+pub enum DensityFunction {
+    Constant(f64),
+    Noise(NoiseSource),
+    Add(Box<DensityFunction>, Box<DensityFunction>),
+    Mul(Box<DensityFunction>, Box<DensityFunction>),
+    Min(Box<DensityFunction>, Box<DensityFunction>),
+    Max(Box<DensityFunction>, Box<DensityFunction>),
+    Clamp {
+        input: Box<DensityFunction>,
+        min: f64,
+        max: f64,
+    },
+    /// Offsets the sample position of `input` by `warp`, sampled once per axis.
+    DomainWarp {
+        input: Box<DensityFunction>,
+        warp: Box<DensityFunction>,
+        strength: f64,
+    },
+    /// Remaps `input` through a cubic Hermite spline.
+    Spline {
+        input: Box<DensityFunction>,
+        points: Vec<SplinePoint>,
+    },
+    /// Blends one function per biome, in `Biome::ALL` order, by the biome
+    /// weights of the sampled column.
+    BiomeBlend(Vec<DensityFunction>),
+    /// Linear gradient along the y axis, from `from_value` at `from_y` to
+    /// `to_value` at `to_y`, extrapolated beyond both ends.
+    YGradient {
+        from_y: f64,
+        to_y: f64,
+        from_value: f64,
+        to_value: f64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Worley,
+    Fbm,
+    Ridged,
+}
+
+#[derive(Clone, Debug)]
+pub struct NoiseSource {
+    pub kind: NoiseKind,
+    /// Added to the world seed, so several sources in one tree can differ.
+    pub seed: u32,
+    pub frequency: f64,
+    /// Only used by the fractal kinds.
+    pub octaves: usize,
+    pub lacunarity: f64,
+    pub persistence: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SplinePoint {
+    pub location: f64,
+    pub value: f64,
+    pub derivative: f64,
+}
+
+impl NoiseSource {
+    pub fn new(kind: NoiseKind, seed: u32, frequency: f64) -> NoiseSource {
+        NoiseSource {
+            kind,
+            seed,
+            frequency,
+            octaves: 6,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+
+    fn compile(&self, world_seed: u32) -> DensitySampler {
+        let seed = world_seed.wrapping_add(self.seed);
+        let frequency = self.frequency;
+        match self.kind {
+            NoiseKind::Perlin => {
+                let noise = Perlin::new(seed);
+                Box::new(move |p| noise.get((p * frequency).to_array()))
+            }
+            NoiseKind::Simplex => {
+                let noise = Simplex::new(seed);
+                Box::new(move |p| noise.get((p * frequency).to_array()))
+            }
+            NoiseKind::Worley => Box::new(move |p| worley(seed, p * frequency)),
+            NoiseKind::Fbm => {
+                let noise = Fbm::<Perlin>::new(seed)
+                    .set_frequency(frequency)
+                    .set_octaves(self.octaves)
+                    .set_lacunarity(self.lacunarity)
+                    .set_persistence(self.persistence);
+                Box::new(move |p| noise.get(p.to_array()))
+            }
+            NoiseKind::Ridged => {
+                let noise = RidgedMulti::<Perlin>::new(seed)
+                    .set_frequency(frequency)
+                    .set_octaves(self.octaves)
+                    .set_lacunarity(self.lacunarity)
+                    .set_persistence(self.persistence);
+                Box::new(move |p| noise.get(p.to_array()))
+            }
+        }
+    }
+}
+
+impl DensityFunction {
+    /// Builds the sampler for this tree, with every noise source seeded from `world_seed`.
+    pub fn compile(&self, world_seed: u32) -> DensitySampler {
+        match self {
+            DensityFunction::Constant(value) => {
+                let value = *value;
+                Box::new(move |_| value)
+            }
+            DensityFunction::Noise(source) => source.compile(world_seed),
+            DensityFunction::Add(a, b) => {
+                let (a, b) = (a.compile(world_seed), b.compile(world_seed));
+                Box::new(move |p| a(p) + b(p))
+            }
+            DensityFunction::Mul(a, b) => {
+                let (a, b) = (a.compile(world_seed), b.compile(world_seed));
+                Box::new(move |p| a(p) * b(p))
+            }
+            DensityFunction::Min(a, b) => {
+                let (a, b) = (a.compile(world_seed), b.compile(world_seed));
+                Box::new(move |p| a(p).min(b(p)))
+            }
+            DensityFunction::Max(a, b) => {
+                let (a, b) = (a.compile(world_seed), b.compile(world_seed));
+                Box::new(move |p| a(p).max(b(p)))
+            }
+            DensityFunction::Clamp { input, min, max } => {
+                let (input, min, max) = (input.compile(world_seed), *min, *max);
+                Box::new(move |p| input(p).clamp(min, max))
+            }
+            DensityFunction::DomainWarp {
+                input,
+                warp,
+                strength,
+            } => {
+                let (input, warp, strength) = (
+                    input.compile(world_seed),
+                    warp.compile(world_seed),
+                    *strength,
+                );
+                // Sample the warp at far apart offsets to get three uncorrelated axes
+                Box::new(move |p| {
+                    let offset = DVec3::new(
+                        warp(p),
+                        warp(p + DVec3::new(5123.7, 0.0, 0.0)),
+                        warp(p + DVec3::new(0.0, 0.0, 9371.3)),
+                    );
+                    input(p + offset * strength)
+                })
+            }
+            DensityFunction::Spline { input, points } => {
+                let input = input.compile(world_seed);
+                let mut points = points.clone();
+                points.sort_by(|a, b| a.location.total_cmp(&b.location));
+                Box::new(move |p| evaluate_spline(&points, input(p)))
+            }
+            DensityFunction::BiomeBlend(biomes) => {
+                debug_assert_eq!(biomes.len(), BIOME_COUNT, "Expected one function per biome");
+                let climate = ClimateMap::new(world_seed);
+                let biomes: Vec<DensitySampler> = biomes
+                    .iter()
+                    .map(|function| function.compile(world_seed))
+                    .collect();
+                Box::new(move |p| {
+                    let mut total_weight = 0.0;
+                    let mut value = 0.0;
+                    for (weight, biome) in climate.weights(p.x, p.z).into_iter().zip(&biomes) {
+                        // Skipping far away biomes saves most of the noise evaluations
+                        if weight > MIN_BLEND_WEIGHT {
+                            total_weight += weight;
+                            value += weight * biome(p);
+                        }
+                    }
+                    value / total_weight
+                })
+            }
+            DensityFunction::YGradient {
+                from_y,
+                to_y,
+                from_value,
+                to_value,
+            } => {
+                let (from_y, to_y, from_value, to_value) = (*from_y, *to_y, *from_value, *to_value);
+                Box::new(move |p| {
+                    from_value + (p.y - from_y) / (to_y - from_y) * (to_value - from_value)
+                })
+            }
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<DensityFunction, String> {
+        if let Some(constant) = value.as_f64() {
+            return Ok(DensityFunction::Constant(constant));
+        }
+
+        let object = value
+            .as_object()
+            .ok_or("Density function must be a number or an object")?;
+        let kind = object
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or("Density function is missing \"type\"")?;
+
+        let child = |key: &str| -> Result<Box<DensityFunction>, String> {
+            let value = object
+                .get(key)
+                .ok_or_else(|| format!("\"{}\" is missing \"{}\"", kind, key))?;
+            Ok(Box::new(DensityFunction::from_json(value)?))
+        };
+
+        let function = match kind {
+            "constant" => DensityFunction::Constant(number(object, kind, "value")?),
+            "perlin" | "simplex" | "worley" | "fbm" | "ridged" => {
+                DensityFunction::Noise(NoiseSource::from_json(object, kind)?)
+            }
+            "add" => DensityFunction::Add(child("a")?, child("b")?),
+            "mul" => DensityFunction::Mul(child("a")?, child("b")?),
+            "min" => DensityFunction::Min(child("a")?, child("b")?),
+            "max" => DensityFunction::Max(child("a")?, child("b")?),
+            "clamp" => DensityFunction::Clamp {
+                input: child("input")?,
+                min: number(object, kind, "min")?,
+                max: number(object, kind, "max")?,
+            },
+            "domain_warp" => DensityFunction::DomainWarp {
+                input: child("input")?,
+                warp: child("warp")?,
+                strength: number(object, kind, "strength")?,
+            },
+            "spline" => {
+                let points = object
+                    .get("points")
+                    .and_then(Value::as_array)
+                    .ok_or("\"spline\" is missing \"points\"")?
+                    .iter()
+                    .map(|point| {
+                        let point = point.as_object().ok_or("Spline points must be objects")?;
+                        Ok(SplinePoint {
+                            location: number(point, "spline point", "location")?,
+                            value: number(point, "spline point", "value")?,
+                            derivative: point
+                                .get("derivative")
+                                .and_then(Value::as_f64)
+                                .unwrap_or(0.0),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                if points.is_empty() {
+                    return Err("\"spline\" needs at least one point".to_string());
+                }
+                DensityFunction::Spline {
+                    input: child("input")?,
+                    points,
+                }
+            }
+            "biome_blend" => {
+                let biomes = Biome::ALL
+                    .iter()
+                    .map(|biome| child(biome.name()).map(|function| *function))
+                    .collect::<Result<Vec<_>, String>>()?;
+                // Every key besides "type" has to name one of the biomes
+                if object.len() != BIOME_COUNT + 1 {
+                    return Err(format!(
+                        "\"biome_blend\" needs exactly one function per biome, one of {}",
+                        Biome::ALL.map(|biome| biome.name()).join(", ")
+                    ));
+                }
+                DensityFunction::BiomeBlend(biomes)
+            }
+            "y_gradient" => {
+                let from_y = number(object, kind, "from_y")?;
+                let to_y = number(object, kind, "to_y")?;
+                // The gradient is a slope between the two heights
+                if from_y == to_y {
+                    return Err(
+                        "\"y_gradient\" needs different \"from_y\" and \"to_y\"".to_string()
+                    );
+                }
+                DensityFunction::YGradient {
+                    from_y,
+                    to_y,
+                    from_value: number(object, kind, "from_value")?,
+                    to_value: number(object, kind, "to_value")?,
+                }
+            }
+            _ => return Err(format!("Unknown density function \"{}\"", kind)),
+        };
+        Ok(function)
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            DensityFunction::Constant(value) => json!(value),
+            DensityFunction::Noise(source) => source.to_json(),
+            DensityFunction::Add(a, b) => binary_json("add", a, b),
+            DensityFunction::Mul(a, b) => binary_json("mul", a, b),
+            DensityFunction::Min(a, b) => binary_json("min", a, b),
+            DensityFunction::Max(a, b) => binary_json("max", a, b),
+            DensityFunction::Clamp { input, min, max } => json!({
+                "type": "clamp",
+                "input": input.to_json(),
+                "min": min,
+                "max": max,
+            }),
+            DensityFunction::DomainWarp {
+                input,
+                warp,
+                strength,
+            } => json!({
+                "type": "domain_warp",
+                "input": input.to_json(),
+                "warp": warp.to_json(),
+                "strength": strength,
+            }),
+            DensityFunction::Spline { input, points } => json!({
+                "type": "spline",
+                "input": input.to_json(),
+                "points": points
+                    .iter()
+                    .map(|point| json!({
+                        "location": point.location,
+                        "value": point.value,
+                        "derivative": point.derivative,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+            DensityFunction::BiomeBlend(biomes) => {
+                let mut value = json!({ "type": "biome_blend" });
+                for (biome, function) in Biome::ALL.iter().zip(biomes) {
+                    value[biome.name()] = function.to_json();
+                }
+                value
+            }
+            DensityFunction::YGradient {
+                from_y,
+                to_y,
+                from_value,
+                to_value,
+            } => json!({
+                "type": "y_gradient",
+                "from_y": from_y,
+                "to_y": to_y,
+                "from_value": from_value,
+                "to_value": to_value,
+            }),
+        }
+    }
+}
+
+impl NoiseSource {
+    fn from_json(object: &Map<String, Value>, kind: &str) -> Result<NoiseSource, String> {
+        let kind_name = kind;
+        let kind = match kind {
+            "perlin" => NoiseKind::Perlin,
+            "simplex" => NoiseKind::Simplex,
+            "worley" => NoiseKind::Worley,
+            "fbm" => NoiseKind::Fbm,
+            "ridged" => NoiseKind::Ridged,
+            _ => return Err(format!("Unknown noise kind \"{}\"", kind)),
+        };
+
+        let mut source = NoiseSource::new(kind, 0, number(object, kind_name, "frequency")?);
+        if let Some(seed) = object.get("seed") {
+            source.seed = seed
+                .as_u64()
+                .and_then(|seed| u32::try_from(seed).ok())
+                .ok_or("Noise \"seed\" must be an unsigned 32-bit integer")?;
+        }
+        if let Some(octaves) = object.get("octaves") {
+            source.octaves = octaves
+                .as_u64()
+                .ok_or("Noise \"octaves\" must be an unsigned integer")?
+                as usize;
+        }
+        if let Some(lacunarity) = object.get("lacunarity") {
+            source.lacunarity = lacunarity
+                .as_f64()
+                .ok_or("Noise \"lacunarity\" must be a number")?;
+        }
+        if let Some(persistence) = object.get("persistence") {
+            source.persistence = persistence
+                .as_f64()
+                .ok_or("Noise \"persistence\" must be a number")?;
+        }
+        Ok(source)
+    }
+
+    fn to_json(&self) -> Value {
+        let kind = match self.kind {
+            NoiseKind::Perlin => "perlin",
+            NoiseKind::Simplex => "simplex",
+            NoiseKind::Worley => "worley",
+            NoiseKind::Fbm => "fbm",
+            NoiseKind::Ridged => "ridged",
+        };
+        let mut value = json!({
+            "type": kind,
+            "seed": self.seed,
+            "frequency": self.frequency,
+        });
+        if matches!(self.kind, NoiseKind::Fbm | NoiseKind::Ridged) {
+            value["octaves"] = json!(self.octaves);
+            value["lacunarity"] = json!(self.lacunarity);
+            value["persistence"] = json!(self.persistence);
+        }
+        value
+    }
+}
+
+fn binary_json(kind: &str, a: &DensityFunction, b: &DensityFunction) -> Value {
+    json!({
+        "type": kind,
+        "a": a.to_json(),
+        "b": b.to_json(),
+    })
+}
+
+fn number(object: &Map<String, Value>, kind: &str, key: &str) -> Result<f64, String> {
+    object
+        .get(key)
+        .and_then(Value::as_f64)
+        .ok_or_else(|| format!("\"{}\" needs a number \"{}\"", kind, key))
+}
+
+/// Cubic Hermite interpolation between sorted points, extrapolated linearly
+/// along the end derivatives.
+fn evaluate_spline(points: &[SplinePoint], x: f64) -> f64 {
+    let first = points[0];
+    let last = points[points.len() - 1];
+    if x <= first.location {
+        return first.value + (x - first.location) * first.derivative;
+    }
+    if x >= last.location {
+        return last.value + (x - last.location) * last.derivative;
+    }
+
+    let segment = points
+        .windows(2)
+        .find(|pair| x <= pair[1].location)
+        .expect("x lies inside the spline range");
+    let (p0, p1) = (segment[0], segment[1]);
+    let width = p1.location - p0.location;
+    let t = (x - p0.location) / width;
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    (2.0 * t3 - 3.0 * t2 + 1.0) * p0.value
+        + (t3 - 2.0 * t2 + t) * width * p0.derivative
+        + (-2.0 * t3 + 3.0 * t2) * p1.value
+        + (t3 - t2) * width * p1.derivative
+}
+
+/// Cellular noise: distance to the nearest of one pseudo-random feature point per
+/// unit cell, roughly in `0..1`.
+fn worley(seed: u32, p: DVec3) -> f64 {
+    let cell = p.floor();
+    let mut nearest = f64::MAX;
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let neighbour = cell + DVec3::new(x as f64, y as f64, z as f64);
+                let feature = neighbour + hash_to_unit_vector(seed, neighbour);
+                nearest = nearest.min(feature.distance_squared(p));
+            }
+        }
+    }
+    nearest.sqrt()
+}
+
+fn hash_to_unit_vector(seed: u32, cell: DVec3) -> DVec3 {
+    let mut hash = seed as u64;
+    for coordinate in [cell.x, cell.y, cell.z] {
+        hash = splitmix64(hash ^ (coordinate as i64 as u64));
+    }
+    let unit = |bits: u64| (bits & 0xFFFF) as f64 / 65535.0;
+    DVec3::new(unit(hash), unit(hash >> 16), unit(hash >> 32))
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn y_gradient_json(from_y: f64, to_y: f64) -> Value {
+        json!({
+            "type": "y_gradient",
+            "from_y": from_y,
+            "to_y": to_y,
+            "from_value": 1.0,
+            "to_value": -1.0,
+        })
+    }
+
+    #[test]
+    fn y_gradient_needs_two_heights() {
+        assert!(DensityFunction::from_json(&y_gradient_json(10.0, 10.0)).is_err());
+
+        let gradient = DensityFunction::from_json(&y_gradient_json(10.0, 20.0))
+            .unwrap()
+            .compile(0);
+        assert_eq!(gradient(DVec3::new(0.0, 15.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn from_json_rejects_unknown_names() {
+        let mut noise = NoiseSource::new(NoiseKind::Perlin, 0, 0.1).to_json();
+        let object = noise.as_object().unwrap();
+        assert!(NoiseSource::from_json(object, "perlin").is_ok());
+        assert!(NoiseSource::from_json(object, "riged").is_err());
+        noise["type"] = json!("riged");
+        assert!(DensityFunction::from_json(&noise).is_err());
+
+        let mut blend =
+            DensityFunction::BiomeBlend(vec![DensityFunction::Constant(1.0); BIOME_COUNT])
+                .to_json();
+        assert!(DensityFunction::from_json(&blend).is_ok());
+        blend["jungle"] = json!(1.0);
+        assert!(DensityFunction::from_json(&blend).is_err());
+        blend.as_object_mut().unwrap().remove("jungle");
+        blend.as_object_mut().unwrap().remove(Biome::ALL[0].name());
+        assert!(DensityFunction::from_json(&blend).is_err());
+    }
+
+    #[test]
+    fn to_json_round_trips() {
+        let noise =
+            |kind, seed| Box::new(DensityFunction::Noise(NoiseSource::new(kind, seed, 0.05)));
+        let mut fbm = NoiseSource::new(NoiseKind::Fbm, 3, 0.02);
+        fbm.octaves = 3;
+        fbm.lacunarity = 2.5;
+        fbm.persistence = 0.4;
+        let shape = DensityFunction::Add(
+            Box::new(DensityFunction::Clamp {
+                input: Box::new(DensityFunction::DomainWarp {
+                    input: Box::new(DensityFunction::Noise(fbm)),
+                    warp: noise(NoiseKind::Simplex, 1),
+                    strength: 4.0,
+                }),
+                min: -0.5,
+                max: 0.8,
+            }),
+            Box::new(DensityFunction::Mul(
+                Box::new(DensityFunction::Spline {
+                    input: noise(NoiseKind::Worley, 2),
+                    points: vec![
+                        SplinePoint {
+                            location: 0.0,
+                            value: 1.0,
+                            derivative: 0.0,
+                        },
+                        SplinePoint {
+                            location: 1.0,
+                            value: -1.0,
+                            derivative: 2.0,
+                        },
+                    ],
+                }),
+                Box::new(DensityFunction::Min(
+                    noise(NoiseKind::Perlin, 4),
+                    Box::new(DensityFunction::Max(
+                        noise(NoiseKind::Ridged, 5),
+                        Box::new(DensityFunction::Constant(0.25)),
+                    )),
+                )),
+            )),
+        );
+        let gradient = DensityFunction::from_json(&y_gradient_json(0.0, 40.0)).unwrap();
+        let original = DensityFunction::BiomeBlend(
+            (0..BIOME_COUNT)
+                .map(|biome| {
+                    DensityFunction::Add(
+                        Box::new(DensityFunction::Mul(
+                            Box::new(DensityFunction::Constant(biome as f64 + 1.0)),
+                            Box::new(shape.clone()),
+                        )),
+                        Box::new(gradient.clone()),
+                    )
+                })
+                .collect(),
+        );
+
+        let reloaded = DensityFunction::from_json(&original.to_json()).unwrap();
+        let (original, reloaded) = (original.compile(7), reloaded.compile(7));
+        for k in 0..64 {
+            let k = k as f64;
+            let p = DVec3::new(k * 37.3 - 900.0, k * 1.7 - 20.0, 500.0 - k * 23.9);
+            assert_eq!(original(p), reloaded(p), "{}", p);
+        }
+    }
+}
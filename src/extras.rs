@@ -4,7 +4,16 @@ use image::GenericImageView;
 #[derive(Clone)]
 pub struct Vertex {
     pub(crate) pos: [f32; 3],
+    pub(crate) normal: [f32; 3],
+    /// Stored texture coordinates. Optional for terrain, which is textured
+    /// triplanarly unless drawn with `use_tex_coords`. Terrain meshes always
+    /// compute and upload them, so they cost 8 bytes per vertex even when
+    /// unused.
     pub(crate) tex_coords: [f32; 2],
+    /// `Biome` id of the column the vertex lies in.
+    pub(crate) biome: f32,
+    /// `Material` id of the solid side of the surface.
+    pub(crate) material: f32,
 }
 
 pub fn load_image_bytes(path: &str) -> (Vec<u8>, u32, u32) {
@@ -0,0 +1,99 @@
+use crate::aabb::Aabb;
+use glam::{Mat4, Vec3, Vec4};
+
+/// The six planes bounding what a camera sees, each pointing inwards.
+pub struct Frustum {
+    /// Plane normal in `xyz` and distance in `w`; a point is inside a plane
+    /// when `dot(normal, point) + w >= 0`.
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a `projection * view` matrix (Gribb–Hartmann).
+    pub fn from_view_projection(view_projection: Mat4) -> Frustum {
+        let rows = [
+            view_projection.row(0),
+            view_projection.row(1),
+            view_projection.row(2),
+            view_projection.row(3),
+        ];
+        let planes = [
+            rows[3] + rows[0], // left
+            rows[3] - rows[0], // right
+            rows[3] + rows[1], // bottom
+            rows[3] - rows[1], // top
+            rows[3] + rows[2], // near
+            rows[3] - rows[2], // far
+        ]
+        .map(|plane| plane / plane.truncate().length());
+        Frustum { planes }
+    }
+
+    /// Whether any part of the box may be visible. Conservative: boxes near a
+    /// frustum corner can pass without actually being inside.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let normal = plane.truncate();
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Camera at the origin looking down -z with a 90° field of view, so the
+    /// frustum is 10 units wide each way at a distance of 10.
+    fn frustum() -> Frustum {
+        let projection = Mat4::perspective_rh_gl(90.0_f32.to_radians(), 1.0, 1.0, 100.0);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        Frustum::from_view_projection(projection * view)
+    }
+
+    /// Unit box around `center`.
+    fn unit_box(center: Vec3) -> Aabb {
+        Aabb::new(center - 0.5, center + 0.5)
+    }
+
+    #[test]
+    fn keeps_boxes_inside() {
+        let frustum = frustum();
+        assert!(frustum.intersects(&unit_box(Vec3::new(0.0, 0.0, -10.0))));
+        assert!(frustum.intersects(&unit_box(Vec3::new(8.0, -8.0, -10.0))));
+        // Bigger than the whole frustum
+        assert!(frustum.intersects(&Aabb::new(Vec3::splat(-500.0), Vec3::splat(500.0))));
+    }
+
+    #[test]
+    fn culls_boxes_outside_each_plane() {
+        let frustum = frustum();
+        for (plane, center) in [
+            ("left", Vec3::new(-12.0, 0.0, -10.0)),
+            ("right", Vec3::new(12.0, 0.0, -10.0)),
+            ("bottom", Vec3::new(0.0, -12.0, -10.0)),
+            ("top", Vec3::new(0.0, 12.0, -10.0)),
+            ("near", Vec3::new(0.0, 0.0, -0.2)),
+            ("far", Vec3::new(0.0, 0.0, -101.0)),
+        ] {
+            assert!(!frustum.intersects(&unit_box(center)), "{}", plane);
+        }
+        // Behind the camera
+        assert!(!frustum.intersects(&unit_box(Vec3::new(0.0, 0.0, 10.0))));
+    }
+
+    #[test]
+    fn keeps_boxes_straddling_a_plane() {
+        let frustum = frustum();
+        for (plane, center) in [
+            ("left", Vec3::new(-10.0, 0.0, -10.0)),
+            ("top", Vec3::new(0.0, 10.0, -10.0)),
+            ("near", Vec3::new(0.0, 0.0, -1.0)),
+            ("far", Vec3::new(0.0, 0.0, -100.0)),
+        ] {
+            assert!(frustum.intersects(&unit_box(center)), "{}", plane);
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use miniquad::{KeyCode, MouseButton};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Default keymap, shipped with the game.
+pub const KEYMAP_PATH: &str = "./assets/keymap.json";
+/// Bindings changed at runtime, on top of the default keymap.
+pub const USER_KEYMAP_PATH: &str = "./keymap.json";
+
+/// Something the player can do, bound to keys and mouse buttons by the keymap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    /// Held to place building pieces without snapping.
+    FreePlacement,
+    Dig,
+    Fill,
+    Place,
+    Remove,
+    RotatePiece,
+    NextMaterial,
+    /// Picks a brush in terrain mode and a piece in build mode.
+    Slot1,
+    Slot2,
+    Slot3,
+    Slot4,
+    Slot5,
+    Slot6,
+    ToggleBuildMode,
+    ToggleNoclip,
+    /// Releases the mouse cursor, or captures it again.
+    ToggleMouseCapture,
+    Screenshot,
+    /// Starts rebinding: the next input picks the binding to change, the one
+    /// after that replaces it.
+    Rebind,
+}
+
+impl Action {
+    pub const ALL: [Action; 23] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::FreePlacement,
+        Action::Dig,
+        Action::Fill,
+        Action::Place,
+        Action::Remove,
+        Action::RotatePiece,
+        Action::NextMaterial,
+        Action::Slot1,
+        Action::Slot2,
+        Action::Slot3,
+        Action::Slot4,
+        Action::Slot5,
+        Action::Slot6,
+        Action::ToggleBuildMode,
+        Action::ToggleNoclip,
+        Action::ToggleMouseCapture,
+        Action::Screenshot,
+        Action::Rebind,
+    ];
+
+    /// Name of the action in the keymap.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveForward => "move_forward",
+            Action::MoveBackward => "move_backward",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Jump => "jump",
+            Action::FreePlacement => "free_placement",
+            Action::Dig => "dig",
+            Action::Fill => "fill",
+            Action::Place => "place",
+            Action::Remove => "remove",
+            Action::RotatePiece => "rotate_piece",
+            Action::NextMaterial => "next_material",
+            Action::Slot1 => "slot_1",
+            Action::Slot2 => "slot_2",
+            Action::Slot3 => "slot_3",
+            Action::Slot4 => "slot_4",
+            Action::Slot5 => "slot_5",
+            Action::Slot6 => "slot_6",
+            Action::ToggleBuildMode => "toggle_build_mode",
+            Action::ToggleNoclip => "toggle_noclip",
+            Action::ToggleMouseCapture => "toggle_mouse_capture",
+            Action::Screenshot => "screenshot",
+            Action::Rebind => "rebind",
+        }
+    }
+}
+
+/// A key or mouse button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+const MOUSE_BUTTONS: [(MouseButton, &str); 3] = [
+    (MouseButton::Left, "MouseLeft"),
+    (MouseButton::Right, "MouseRight"),
+    (MouseButton::Middle, "MouseMiddle"),
+];
+
+/// Every key that can be bound. Keys are named like their `KeyCode` variant.
+#[rustfmt::skip]
+const KEY_CODES: [KeyCode; 121] = {
+    use KeyCode::*;
+    [
+        Space, Apostrophe, Comma, Minus, Period, Slash, Key0, Key1, Key2, Key3, Key4, Key5, Key6,
+        Key7, Key8, Key9, Semicolon, Equal, A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R,
+        S, T, U, V, W, X, Y, Z, LeftBracket, Backslash, RightBracket, GraveAccent, World1, World2,
+        Escape, Enter, Tab, Backspace, Insert, Delete, Right, Left, Down, Up, PageUp, PageDown,
+        Home, End, CapsLock, ScrollLock, NumLock, PrintScreen, Pause, F1, F2, F3, F4, F5, F6, F7,
+        F8, F9, F10, F11, F12, F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24, F25,
+        Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9, KpDecimal, KpDivide, KpMultiply,
+        KpSubtract, KpAdd, KpEnter, KpEqual, LeftShift, LeftControl, LeftAlt, LeftSuper,
+        RightShift, RightControl, RightAlt, RightSuper, Menu, Back,
+    ]
+};
+
+impl Binding {
+    pub fn parse(name: &str) -> Option<Binding> {
+        MOUSE_BUTTONS
+            .iter()
+            .find(|(_, button_name)| *button_name == name)
+            .map(|(button, _)| Binding::Mouse(*button))
+            .or_else(|| {
+                KEY_CODES
+                    .iter()
+                    .find(|key| format!("{:?}", key) == name)
+                    .map(|key| Binding::Key(*key))
+            })
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Mouse(button) => MOUSE_BUTTONS
+                .iter()
+                .find(|(other, _)| *other == button)
+                .map_or_else(|| format!("{:?}", button), |(_, name)| name.to_string()),
+        }
+    }
+}
+
+enum RebindState {
+    Idle,
+    /// Waiting for the binding to replace.
+    ChooseOld,
+    /// Waiting for the binding that replaces this one.
+    ChooseNew(Binding),
+}
+
+/// Maps key and mouse events to actions through the keymap, and tracks what is
+/// held down.
+pub struct Input {
+    user_path: Option<String>,
+    defaults: HashMap<Action, Vec<Binding>>,
+    bindings: HashMap<Action, Vec<Binding>>,
+    held: HashSet<Binding>,
+    rebind: RebindState,
+}
+
+impl Input {
+    /// Loads the keymap at `path`, with the actions mentioned in the one at
+    /// `user_path`, if there is one, bound the way it says instead. Rebinding
+    /// saves to `user_path`, and without one the changes are lost on exit.
+    pub fn load(path: &str, user_path: Option<&str>) -> Input {
+        let json = std::fs::read_to_string(path).expect("Failed to read keymap");
+        let defaults = Input::from_json(&json).expect("Failed to parse keymap");
+        let mut bindings = defaults.clone();
+        match user_path.map(std::fs::read_to_string) {
+            Some(Ok(json)) => {
+                bindings.extend(Input::from_json(&json).expect("Failed to parse user keymap"))
+            }
+            Some(Err(err)) if err.kind() != std::io::ErrorKind::NotFound => {
+                panic!("Failed to read user keymap: {}", err)
+            }
+            _ => {}
+        }
+        Input {
+            user_path: user_path.map(str::to_string),
+            defaults,
+            bindings,
+            held: HashSet::new(),
+            rebind: RebindState::Idle,
+        }
+    }
+
+    /// Parses a keymap: an object from action names to arrays of key and mouse
+    /// button names. Actions it doesn't mention are unbound.
+    fn from_json(json: &str) -> Result<HashMap<Action, Vec<Binding>>, String> {
+        let value: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let object = value.as_object().ok_or("Keymap must be an object")?;
+
+        let mut bindings = HashMap::new();
+        for (name, value) in object {
+            let action = Action::ALL
+                .into_iter()
+                .find(|action| action.name() == name)
+                .ok_or_else(|| format!("Unknown action \"{}\"", name))?;
+            let names = value
+                .as_array()
+                .ok_or_else(|| format!("\"{}\" must be an array of keys", name))?;
+            let action_bindings = names
+                .iter()
+                .map(|binding| {
+                    let binding = binding
+                        .as_str()
+                        .ok_or_else(|| format!("Bindings of \"{}\" must be strings", name))?;
+                    Binding::parse(binding).ok_or_else(|| format!("Unknown key \"{}\"", binding))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            bindings.insert(action, action_bindings);
+        }
+        Ok(bindings)
+    }
+
+    /// The bindings of every action bound differently from the default keymap.
+    fn overrides_json(&self) -> Value {
+        let mut object = Map::new();
+        for action in Action::ALL {
+            if self.bindings.get(&action) == self.defaults.get(&action) {
+                continue;
+            }
+            let names = self
+                .bindings
+                .get(&action)
+                .into_iter()
+                .flatten()
+                .map(|binding| Value::String(binding.name()))
+                .collect();
+            object.insert(action.name().to_string(), Value::Array(names));
+        }
+        Value::Object(object)
+    }
+
+    /// Whether any binding of the action is held down.
+    pub fn is_held(&self, action: Action) -> bool {
+        self.bindings
+            .get(&action)
+            .is_some_and(|bindings| bindings.iter().any(|binding| self.held.contains(binding)))
+    }
+
+    /// Records a key or button going down, and returns the actions it triggers.
+    /// While rebinding, the press is used for that instead and triggers nothing.
+    pub fn press(&mut self, binding: Binding) -> Vec<Action> {
+        self.held.insert(binding);
+
+        match self.rebind {
+            RebindState::Idle => {}
+            RebindState::ChooseOld => {
+                println!("Press the new binding for {}", binding.name());
+                self.rebind = RebindState::ChooseNew(binding);
+                return Vec::new();
+            }
+            RebindState::ChooseNew(old) => {
+                self.rebind = RebindState::Idle;
+                self.replace_binding(old, binding);
+                return Vec::new();
+            }
+        }
+
+        let actions: Vec<Action> = Action::ALL
+            .into_iter()
+            .filter(|action| {
+                self.bindings
+                    .get(action)
+                    .is_some_and(|bindings| bindings.contains(&binding))
+            })
+            .collect();
+        if actions.contains(&Action::Rebind) {
+            println!("Press the binding to change");
+            self.rebind = RebindState::ChooseOld;
+        }
+        actions
+    }
+
+    pub fn release(&mut self, binding: Binding) {
+        self.held.remove(&binding);
+    }
+
+    /// Forgets every held key and button, for when their releases can't be seen.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Moves every action bound to `old` over to `new`, and saves the changes to
+    /// the user keymap.
+    fn replace_binding(&mut self, old: Binding, new: Binding) {
+        for bindings in self.bindings.values_mut() {
+            for binding in bindings.iter_mut().filter(|binding| **binding == old) {
+                *binding = new;
+            }
+        }
+        println!("Rebound {} to {}", old.name(), new.name());
+
+        let Some(user_path) = &self.user_path else {
+            return;
+        };
+        let json = serde_json::to_string_pretty(&self.overrides_json()).unwrap();
+        if let Err(err) = std::fs::write(user_path, json + "\n") {
+            println!("Failed to save keymap: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_json_parses_keys_and_mouse_buttons() {
+        let bindings =
+            Input::from_json(r#"{ "jump": ["Space", "MouseMiddle"], "dig": [] }"#).unwrap();
+        assert_eq!(
+            bindings[&Action::Jump],
+            vec![
+                Binding::Key(KeyCode::Space),
+                Binding::Mouse(MouseButton::Middle)
+            ]
+        );
+        assert!(bindings[&Action::Dig].is_empty());
+        assert!(!bindings.contains_key(&Action::Place));
+    }
+
+    #[test]
+    fn from_json_rejects_unknown_names() {
+        for json in [
+            r#"{ "fly": ["Space"] }"#,
+            r#"{ "jump": ["Spacebar"] }"#,
+            r#"{ "jump": "Space" }"#,
+            r#"{ "jump": [32] }"#,
+            r#"["jump"]"#,
+        ] {
+            assert!(Input::from_json(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn rebinding_replaces_the_old_binding_and_is_saved_separately() {
+        let user_path = std::env::temp_dir().join(format!("keymap-{}.json", std::process::id()));
+        let user_path = user_path.to_str().unwrap();
+        let _ = std::fs::remove_file(user_path);
+
+        let w = Binding::Key(KeyCode::W);
+        let up = Binding::Key(KeyCode::Up);
+        let mut input = Input::load(KEYMAP_PATH, Some(user_path));
+        assert_eq!(input.press(w), vec![Action::MoveForward]);
+        assert!(input.press(up).is_empty());
+
+        assert_eq!(input.press(Binding::Key(KeyCode::F1)), vec![Action::Rebind]);
+        assert!(input.press(w).is_empty());
+        assert!(input.press(up).is_empty());
+
+        assert!(input.press(w).is_empty());
+        assert_eq!(input.press(up), vec![Action::MoveForward]);
+        input.release_all();
+        input.press(up);
+        assert!(input.is_held(Action::MoveForward));
+
+        // Only the change is saved, and loaded again on top of the defaults
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(user_path).unwrap()).unwrap();
+        assert_eq!(saved, serde_json::json!({ "move_forward": ["Up"] }));
+        let mut reloaded = Input::load(KEYMAP_PATH, Some(user_path));
+        assert_eq!(reloaded.press(up), vec![Action::MoveForward]);
+        assert_eq!(
+            reloaded.press(Binding::Key(KeyCode::S)),
+            vec![Action::MoveBackward]
+        );
+        std::fs::remove_file(user_path).unwrap();
+    }
+}
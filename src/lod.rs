@@ -0,0 +1,126 @@
+use glam::IVec3;
+
+/// Chunk distance from the camera, in chunks along the furthest axis, below
+/// which each level of detail is used. Chunks further out use `MAX_STEP`.
+const LOD_DISTANCES: [i32; 3] = [2, 4, 8];
+/// World units between samples of the coarsest chunks.
+const MAX_STEP: i32 = 8;
+
+/// Sample spacing a chunk is meshed at, plus that of every chunk touching it,
+/// which decides where it needs transition cells.
+///
+/// Chunks touching at a point are at most one chunk apart in distance, so their
+/// steps differ by at most a factor of two, and a chunk never has both finer
+/// and coarser neighbours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkLod {
+    /// World units between samples: 1, 2, 4 or 8.
+    pub step: i32,
+    /// Step of every chunk in the 3×3×3 block centred on this one, by
+    /// `neighbour_index`. Edge and corner neighbours count too, as a chunk
+    /// sharing only an edge still meshes that edge at its own step.
+    neighbour_steps: [i32; 27],
+}
+
+impl ChunkLod {
+    /// Level of detail of the chunk at `coord` when the camera is in chunk `center`.
+    pub fn new(coord: IVec3, center: IVec3) -> ChunkLod {
+        let mut neighbour_steps = [0; 27];
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let offset = IVec3::new(x, y, z);
+                    neighbour_steps[neighbour_index(offset)] = lod_step(coord + offset, center);
+                }
+            }
+        }
+        ChunkLod {
+            step: lod_step(coord, center),
+            neighbour_steps,
+        }
+    }
+
+    /// Step of the chunk at `offset` from this one, with every component in `-1..=1`.
+    pub fn neighbour_step(&self, offset: IVec3) -> i32 {
+        self.neighbour_steps[neighbour_index(offset)]
+    }
+
+    /// Whether any chunk touching this one is meshed at a finer step, so its
+    /// border needs transition cells.
+    pub fn has_finer_neighbours(&self) -> bool {
+        self.neighbour_steps.iter().any(|&step| step < self.step)
+    }
+}
+
+fn neighbour_index(offset: IVec3) -> usize {
+    debug_assert!(offset.abs().max_element() <= 1);
+    ((offset.x + 1) * 9 + (offset.y + 1) * 3 + offset.z + 1) as usize
+}
+
+/// Sample spacing of the chunk at `coord`, doubling with every distance band.
+fn lod_step(coord: IVec3, center: IVec3) -> i32 {
+    let distance = (coord - center).abs().max_element();
+    LOD_DISTANCES
+        .iter()
+        .position(|&limit| distance < limit)
+        .map_or(MAX_STEP, |level| 1 << level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_doubles_at_each_distance_band() {
+        let center = IVec3::new(5, -2, 7);
+        let expected = [
+            (0, 1),
+            (1, 1),
+            (2, 2),
+            (3, 2),
+            (4, 4),
+            (7, 4),
+            (8, 8),
+            (12, 8),
+        ];
+        for (distance, step) in expected {
+            for axis in [IVec3::X, IVec3::NEG_Y, IVec3::Z] {
+                assert_eq!(
+                    lod_step(center + axis * distance, center),
+                    step,
+                    "distance {distance}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn step_follows_the_furthest_axis() {
+        let center = IVec3::ZERO;
+        assert_eq!(lod_step(IVec3::new(1, 1, 1), center), 1);
+        assert_eq!(lod_step(IVec3::new(1, 0, -2), center), 2);
+        assert_eq!(lod_step(IVec3::new(-3, 3, 4), center), 4);
+    }
+
+    #[test]
+    fn neighbour_steps_cover_faces_edges_and_corners() {
+        let center = IVec3::ZERO;
+        // On the corner of the step 2 band, diagonal to the last step 1 chunk
+        let lod = ChunkLod::new(IVec3::new(2, 0, 2), center);
+        assert_eq!(lod.step, 2);
+        assert_eq!(lod.neighbour_step(IVec3::ZERO), 2);
+        assert_eq!(lod.neighbour_step(IVec3::NEG_X), 2);
+        assert_eq!(lod.neighbour_step(IVec3::NEG_Z), 2);
+        assert_eq!(lod.neighbour_step(IVec3::new(-1, 0, -1)), 1);
+        assert_eq!(lod.neighbour_step(IVec3::new(-1, 1, -1)), 1);
+        assert_eq!(lod.neighbour_step(IVec3::new(1, 0, 1)), 2);
+        assert!(lod.has_finer_neighbours());
+
+        // One further out, the closest chunks around it are step 2 as well
+        let lod = ChunkLod::new(IVec3::new(3, 0, 3), center);
+        assert_eq!(lod.step, 2);
+        assert_eq!(lod.neighbour_step(IVec3::new(-1, 0, -1)), 2);
+        assert_eq!(lod.neighbour_step(IVec3::new(1, 0, 1)), 4);
+        assert!(!lod.has_finer_neighbours());
+    }
+}
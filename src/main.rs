@@ -1,22 +1,74 @@
 mod stage;
+mod aabb;
+mod building;
+mod lod;
 mod marching_cubes;
+mod material;
+mod material_registry;
+mod raycast;
+mod scalar_field;
 mod scalar_generator;
 mod shader;
+mod snapping;
+mod structure;
 mod data;
+mod density;
 mod extras;
+mod frustum;
+mod input;
+mod bench;
+mod brush;
+mod biome;
 mod camera;
+mod caves;
+mod chunk;
+mod player;
+mod timestep;
+mod transition;
+mod world_config;
 
 use miniquad::*;
 use stage::Stage;
+use world_config::{arg_value, WorldGenConfig};
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    let mut world_config = match arg_value(&args, "--world-config") {
+        Some(path) => WorldGenConfig::load(path),
+        None => WorldGenConfig::default(),
+    };
+    world_config.apply_args(&args);
+
+    if args.iter().any(|arg| arg == "--dump-density") {
+        // Prints the active terrain shape, as a starting point for a custom "density" tree
+        let density = world_config.density_function().to_json();
+        println!("{}", serde_json::to_string_pretty(&density).unwrap());
+        return;
+    }
+
+    if args.iter().any(|arg| arg == "bench") {
+        bench::run(&world_config);
+        return;
+    }
+
+    let tick_rate = match arg_value(&args, "--tick-rate") {
+        Some(value) => value.parse().expect("--tick-rate must be a number"),
+        None => timestep::DEFAULT_TICK_RATE,
+    };
+
+    // Compares against the old top-down texturing. The texture coordinates
+    // are stored in every terrain vertex either way, this only picks whether
+    // the shader reads them
+    let use_tex_coords = args.iter().any(|arg| arg == "--top-down-uvs");
+
     let mut conf = conf::Conf::default();
-    let metal = std::env::args().nth(1).as_deref() == Some("metal");
+    let metal = args.iter().any(|arg| arg == "metal");
     conf.platform.apple_gfx_api = if metal {
         conf::AppleGfxApi::Metal
     } else {
         conf::AppleGfxApi::OpenGl
     };
 
-    miniquad::start(conf, move || Box::new(Stage::new()));
+    miniquad::start(conf, move || Box::new(Stage::new(world_config, tick_rate, use_tex_coords)));
 }
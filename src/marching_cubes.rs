@@ -1,26 +1,122 @@
 use crate::data;
 use crate::extras::Vertex;
+use crate::scalar_field::ScalarField;
+use glam::Vec3;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::ops::Range;
 
+/// Extra samples a scalar field needs on each side of the cells being meshed.
+pub const FIELD_PADDING: usize = 1;
+
+/// Number of x-slices of cells meshed together on one thread.
+const SLAB_SIZE: usize = 8;
+
+/// Meshes `cells`³ cells of a padded scalar field.
+///
+/// The field holds at least `cells + 1 + 2 * FIELD_PADDING` samples per axis, and
+/// the first meshed cell starts `FIELD_PADDING` samples past its origin. Vertices
+/// are emitted in world space, so two chunks meshed from separate fields produce
+/// exactly the same vertices along the face they share.
+///
+/// The field is meshed in slabs along the x axis in parallel, and the slabs are
+/// merged into the same mesh a single serial pass would produce.
 pub fn generate_marching_cubes(
-    grid_size: usize,
-    scalar_field: &Vec<Vec<Vec<f32>>>,
+    scalar_field: &ScalarField,
+    cells: usize,
     threshold: f32,
 ) -> (Vec<Vertex>, Vec<u32>) {
+    generate_marching_cubes_skipping(scalar_field, cells, threshold, |_| false)
+}
+
+/// Like [`generate_marching_cubes`], but leaves out the cells for which `skip`
+/// returns true, so they can be meshed another way.
+pub fn generate_marching_cubes_skipping(
+    scalar_field: &ScalarField,
+    cells: usize,
+    threshold: f32,
+    skip: impl Fn([usize; 3]) -> bool + Sync,
+) -> (Vec<Vertex>, Vec<u32>) {
+    debug_assert!(scalar_field
+        .dimensions()
+        .iter()
+        .all(|&size| size >= cells + 1 + 2 * FIELD_PADDING));
+
+    let slabs: Vec<SlabMesh> = (0..cells)
+        .step_by(SLAB_SIZE)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|start| {
+            mesh_slab(
+                scalar_field,
+                start..(start + SLAB_SIZE).min(cells),
+                cells,
+                threshold,
+                &skip,
+            )
+        })
+        .collect();
+
+    // Merge the slabs in order. A vertex on the face between two slabs is meshed by
+    // both; the first slab keeps it, which is exactly the order a single pass over
+    // the whole field would have created it in.
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut edge_cache: HashMap<usize, u32> = HashMap::new();
+
+    for slab in slabs {
+        let mut remap = Vec::with_capacity(slab.vertices.len());
+        for (vertex, key) in slab.vertices.into_iter().zip(slab.edge_keys) {
+            let index = *edge_cache.entry(key).or_insert_with(|| {
+                vertices.push(vertex);
+                vertices.len() as u32 - 1
+            });
+            remap.push(index);
+        }
+        indices.extend(slab.indices.iter().map(|&index| remap[index as usize]));
+    }
+
+    (vertices, indices)
+}
+
+/// Mesh of a range of x-slices, with the edge key of every vertex so slabs can
+/// be stitched back together.
+struct SlabMesh {
+    vertices: Vec<Vertex>,
+    edge_keys: Vec<usize>,
+    indices: Vec<u32>,
+}
+
+fn mesh_slab(
+    scalar_field: &ScalarField,
+    x_range: Range<usize>,
+    cells: usize,
+    threshold: f32,
+    skip: &impl Fn([usize; 3]) -> bool,
+) -> SlabMesh {
+    let dimensions = scalar_field.dimensions();
+
     let mut vertices: Vec<Vertex> = Vec::new();
     let mut indices: Vec<u32> = Vec::new();
+    // Vertex index of every cube edge the surface crosses, so neighbouring cells
+    // share the vertices on their common edges
+    let mut edge_cache: HashMap<usize, u32> = HashMap::new();
+    let mut edge_keys: Vec<usize> = Vec::new();
+
+    for x in x_range {
+        for y in 0..cells {
+            for z in 0..cells {
+                if skip([x, y, z]) {
+                    continue;
+                }
 
-    for x in 0..grid_size - 1 {
-        for y in 0..grid_size - 1 {
-            for z in 0..grid_size - 1 {
                 let mut cube_index = 0;
                 let mut corner_values = [0.0; 8];
 
-                for i in 0..8 {
-                    let corner_x = x + (i & 1);
-                    let corner_y = y + ((i >> 1) & 1);
-                    let corner_z = z + ((i >> 2) & 1);
-                    corner_values[i] = scalar_field[corner_x][corner_y][corner_z];
-                    if corner_values[i] < threshold {
+                for (i, corner_value) in corner_values.iter_mut().enumerate() {
+                    let [corner_x, corner_y, corner_z] = corner_sample(i, [x, y, z]);
+                    *corner_value = scalar_field.get(corner_x, corner_y, corner_z);
+                    if *corner_value < threshold {
                         cube_index |= 1 << i;
                     }
                 }
@@ -29,48 +125,124 @@ pub fn generate_marching_cubes(
                     continue;
                 }
 
-                let mut edge_vertices = [[0.0; 3]; 12];
-                for i in 0..12 {
+                let mut edge_indices = [0u32; 12];
+                for (i, edge_index) in edge_indices.iter_mut().enumerate() {
                     if (data::EDGE_MASKS[cube_index] & (1 << i)) != 0 {
-                        let [v1, v2] = data::EDGE_VERTEX_INDICES[i];
-                        edge_vertices[i] = interpolate_vertex(
-                            corner_values[v1],
-                            corner_values[v2],
-                            threshold,
-                            corner_position(v1, x, y, z),
-                            corner_position(v2, x, y, z),
-                        );
+                        // Always interpolate from the lower corner, so an edge shared
+                        // by neighbouring cells or chunks gives bit-identical results
+                        let [a, b] = data::EDGE_VERTEX_INDICES[i];
+                        let (v1, v2) = (a.min(b), a.max(b));
+
+                        let key = edge_key(dimensions, v1, v2, [x, y, z]);
+                        *edge_index = *edge_cache.entry(key).or_insert_with(|| {
+                            vertices.push(edge_vertex(
+                                FieldSample::new(scalar_field, corner_sample(v1, [x, y, z])),
+                                FieldSample::new(scalar_field, corner_sample(v2, [x, y, z])),
+                                threshold,
+                            ));
+                            edge_keys.push(key);
+                            vertices.len() as u32 - 1
+                        });
                     }
                 }
 
                 let mut tri = 0;
                 while data::TRIANGULATION_TABLE[cube_index][tri] != -1 {
-                    let mut triangle_indices = Vec::new();
                     for j in 0..3 {
-                        let edge_index = data::TRIANGULATION_TABLE[cube_index][tri + j] as usize;
-                        let vertex_position = edge_vertices[edge_index];
-
-                        let u = vertex_position[0] / 16.0 as f32;
-                        let v = vertex_position[2] / 16.0 as f32;
-
-                        let vertex = Vertex {
-                            pos: vertex_position,
-                            tex_coords: [u, v],
-                        };
-
-                        let index = vertices.len() as u32;
-                        vertices.push(vertex);
-                        triangle_indices.push(index);
+                        let edge = data::TRIANGULATION_TABLE[cube_index][tri + j] as usize;
+                        indices.push(edge_indices[edge]);
                     }
-
-                    indices.extend(triangle_indices);
                     tri += 3;
                 }
             }
         }
     }
 
-    (vertices, indices)
+    SlabMesh {
+        vertices,
+        edge_keys,
+        indices,
+    }
+}
+
+/// A sample of a scalar field, by the field holding it and its index there.
+#[derive(Clone, Copy)]
+pub struct FieldSample<'a> {
+    field: &'a ScalarField,
+    index: [usize; 3],
+}
+
+impl<'a> FieldSample<'a> {
+    pub fn new(field: &'a ScalarField, index: [usize; 3]) -> FieldSample<'a> {
+        FieldSample { field, index }
+    }
+
+    pub fn value(&self) -> f32 {
+        let [x, y, z] = self.index;
+        self.field.get(x, y, z)
+    }
+
+    /// World position of the sample. Integer until the final conversion, so
+    /// neighbouring chunks get exactly the same coordinates.
+    fn position(&self) -> [f32; 3] {
+        self.field.world_position(self.index).as_vec3().to_array()
+    }
+
+    fn gradient(&self) -> Vec3 {
+        let [x, y, z] = self.index;
+        self.field.gradient(x, y, z)
+    }
+}
+
+/// Vertex where the surface crosses the edge between two samples.
+///
+/// `lower` must be the end with the lower coordinate. Always interpolating from
+/// the lower end means an edge shared by neighbouring cells or chunks gives
+/// bit-identical results, even when they read it from different fields.
+pub fn edge_vertex(lower: FieldSample, upper: FieldSample, threshold: f32) -> Vertex {
+    let (value1, value2) = (lower.value(), upper.value());
+    let position = interpolate_vertex(
+        value1,
+        value2,
+        threshold,
+        lower.position(),
+        upper.position(),
+    );
+    let normal = interpolate_normal(
+        value1,
+        value2,
+        threshold,
+        lower.gradient(),
+        upper.gradient(),
+    );
+
+    let [biome_x, _, biome_z] = lower.index;
+    let biome = lower.field.biome(biome_x, biome_z);
+    // The solid end of the edge decides what the surface is made of
+    let solid = if value1 >= value2 { lower } else { upper };
+    let [solid_x, solid_y, solid_z] = solid.index;
+    let material = solid.field.material(solid_x, solid_y, solid_z);
+
+    // Top-down projection, only read when terrain is drawn with stored texture
+    // coordinates but always stored, so the vertex layout stays the same
+    let tex_coords = [position[0], position[2]];
+
+    Vertex {
+        pos: position,
+        normal,
+        tex_coords,
+        biome: biome as u8 as f32,
+        material: material as u8 as f32,
+    }
+}
+
+/// Identifies a cube edge by the field index of its lower corner and its axis.
+fn edge_key(dimensions: [usize; 3], v1: usize, v2: usize, cell: [usize; 3]) -> usize {
+    let axis = (v1 ^ v2).trailing_zeros() as usize;
+    let x = cell[0] + (v1 & 1);
+    let y = cell[1] + ((v1 >> 1) & 1);
+    let z = cell[2] + ((v1 >> 2) & 1);
+    ((x * dimensions[1] + y) * dimensions[2] + z) * 3 + axis
 }
 
 fn interpolate_vertex(
@@ -88,10 +260,93 @@ fn interpolate_vertex(
     ]
 }
 
-fn corner_position(corner: usize, x: usize, y: usize, z: usize) -> [f32; 3] {
+/// Surface normal at the crossing point, blended from the field gradient at both
+/// corners. Density falls off towards the air, so the normal is the negated gradient.
+fn interpolate_normal(
+    value1: f32,
+    value2: f32,
+    threshold: f32,
+    gradient1: Vec3,
+    gradient2: Vec3,
+) -> [f32; 3] {
+    let t = (threshold - value1) / (value2 - value1);
+    (-gradient1.lerp(gradient2, t))
+        .normalize_or_zero()
+        .to_array()
+}
+
+/// Field sample index of a cell corner, including the padding.
+fn corner_sample(corner: usize, cell: [usize; 3]) -> [usize; 3] {
     [
-        (x + (corner & 1)) as f32,
-        (y + ((corner >> 1) & 1)) as f32,
-        (z + ((corner >> 2) & 1)) as f32,
+        FIELD_PADDING + cell[0] + (corner & 1),
+        FIELD_PADDING + cell[1] + ((corner >> 1) & 1),
+        FIELD_PADDING + cell[2] + ((corner >> 2) & 1),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar_generator::generate_scalar_field;
+    use crate::world_config::WorldGenConfig;
+    use glam::IVec3;
+
+    const CELLS: usize = 16;
+    const THRESHOLD: f32 = 0.9;
+
+    /// Padded field of the chunk of `CELLS`³ cells at `coord`, cutting through
+    /// the ground of the default world.
+    fn chunk_field(coord: IVec3) -> ScalarField {
+        let origin = coord * CELLS as i32 - IVec3::splat(FIELD_PADDING as i32);
+        let size = CELLS + 1 + 2 * FIELD_PADDING;
+        generate_scalar_field(&WorldGenConfig::default(), [size; 3], origin, 1)
+    }
+
+    /// Bit patterns of position and normal, so vertices compare exactly.
+    fn vertex_bits(vertex: &Vertex) -> [u32; 6] {
+        let [x, y, z] = vertex.pos;
+        let [nx, ny, nz] = vertex.normal;
+        [x, y, z, nx, ny, nz].map(f32::to_bits)
+    }
+
+    #[test]
+    fn neighbouring_chunks_share_border_vertices_exactly() {
+        let left = chunk_field(IVec3::new(0, 6, 0));
+        let right = chunk_field(IVec3::new(1, 6, 0));
+        let face = CELLS as f32;
+
+        let border_vertices = |field: &ScalarField| {
+            let (vertices, _) = generate_marching_cubes(field, CELLS, THRESHOLD);
+            let mut border: Vec<[u32; 6]> = vertices
+                .iter()
+                .filter(|vertex| vertex.pos[0] == face)
+                .map(vertex_bits)
+                .collect();
+            border.sort_unstable();
+            border
+        };
+
+        let left_border = border_vertices(&left);
+        assert!(!left_border.is_empty(), "the surface should cross the face");
+        assert_eq!(left_border, border_vertices(&right));
+    }
+
+    #[test]
+    fn parallel_slabs_match_a_serial_pass() {
+        // Several slabs, the last one shorter than SLAB_SIZE
+        let cells = SLAB_SIZE * 2 + 3;
+        let origin = IVec3::new(0, 6 * CELLS as i32, 0) - IVec3::splat(FIELD_PADDING as i32);
+        let size = cells + 1 + 2 * FIELD_PADDING;
+        let field = generate_scalar_field(&WorldGenConfig::default(), [size; 3], origin, 1);
+
+        let (vertices, indices) = generate_marching_cubes(&field, cells, THRESHOLD);
+        let serial = mesh_slab(&field, 0..cells, cells, THRESHOLD, &|_| false);
+
+        assert!(!indices.is_empty());
+        assert_eq!(
+            vertices.iter().map(vertex_bits).collect::<Vec<_>>(),
+            serial.vertices.iter().map(vertex_bits).collect::<Vec<_>>()
+        );
+        assert_eq!(indices, serial.indices);
+    }
+}
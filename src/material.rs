@@ -0,0 +1,96 @@
+use crate::biome::Biome;
+use crate::scalar_field::ScalarField;
+use crate::world_config::WorldGenConfig;
+use noise::{NoiseFn, Perlin};
+use rayon::prelude::*;
+
+/// Depth below the surface, in world units, where the top layer ends.
+const TOP_LAYER_DEPTH: f32 = 1.5;
+/// Depth below the surface where the soil layer gives way to stone.
+const SOIL_DEPTH: f32 = 4.0;
+/// Surfaces with a normal flatter than this (its y component) are bare rock.
+const CLIFF_NORMAL_Y: f32 = 0.6;
+
+const ORE_FREQUENCY: f64 = 0.06;
+/// Ore noise value above which stone turns into ore.
+const ORE_THRESHOLD: f64 = 0.55;
+
+pub const MATERIAL_COUNT: usize = 6;
+
+/// What a solid sample of the terrain is made of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Material {
+    Grass,
+    Dirt,
+    Stone,
+    Sand,
+    Snow,
+    Ore,
+}
+
+impl Material {
+    pub const ALL: [Material; MATERIAL_COUNT] = [
+        Material::Grass,
+        Material::Dirt,
+        Material::Stone,
+        Material::Sand,
+        Material::Snow,
+        Material::Ore,
+    ];
+
+    /// Name of the material in the material manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            Material::Grass => "grass",
+            Material::Dirt => "dirt",
+            Material::Stone => "stone",
+            Material::Sand => "sand",
+            Material::Snow => "snow",
+            Material::Ore => "ore",
+        }
+    }
+}
+
+/// Decides the material of every sample from its depth below the surface, the
+/// slope of the terrain, the biome and ore vein noise.
+///
+/// Runs before caves are carved, so depth and slope describe the terrain itself
+/// and cave walls come out as stone rather than grass.
+pub fn paint_materials(scalar_field: &mut ScalarField, config: &WorldGenConfig, threshold: f32) {
+    let ore_noise = Perlin::new(config.seed.wrapping_add(606));
+    // Density drops by roughly this much per world unit above the ground
+    let density_per_unit = (config.falloff * config.global_scale) as f32;
+
+    let field = &*scalar_field;
+    let materials = (0..field.len())
+        .into_par_iter()
+        .map(|index| {
+            let [x, y, z] = field.sample_position(index);
+            let depth = (field.get(x, y, z) - threshold) / density_per_unit;
+
+            if depth > SOIL_DEPTH {
+                let world = field.world_position([x, y, z]).as_dvec3() * ORE_FREQUENCY;
+                return if ore_noise.get(world.to_array()) > ORE_THRESHOLD {
+                    Material::Ore
+                } else {
+                    Material::Stone
+                };
+            }
+
+            let normal_y = -field.gradient(x, y, z).normalize_or_zero().y;
+            if normal_y < CLIFF_NORMAL_Y {
+                return Material::Stone;
+            }
+
+            match (field.biome(x, z), depth < TOP_LAYER_DEPTH) {
+                (Biome::Desert, _) => Material::Sand,
+                (Biome::Snow, true) => Material::Snow,
+                (Biome::Plains | Biome::Hills, true) => Material::Grass,
+                _ => Material::Dirt,
+            }
+        })
+        .collect();
+
+    scalar_field.set_materials(materials);
+}
@@ -0,0 +1,135 @@
+use crate::extras::load_image_bytes;
+use image::{imageops, ImageBuffer, Rgba, RgbaImage};
+use miniquad::*;
+use serde_json::Value;
+use std::path::Path;
+
+pub const MANIFEST_PATH: &str = "./assets/materials.json";
+
+/// Width of the wrapped border around every atlas tile, relative to the tile
+/// size. Keeps filtering and lower mip levels from bleeding between tiles.
+const GUTTER_FRACTION: u32 = 8;
+
+/// A material described in the manifest, with its tile in the texture atlas.
+pub struct MaterialEntry {
+    pub name: String,
+    texture_path: String,
+}
+
+/// All materials that can be rendered, loaded from the JSON manifest under `assets/`.
+/// The index of a material in the registry is its layer in the texture atlas.
+pub struct MaterialRegistry {
+    tile_size: u32,
+    entries: Vec<MaterialEntry>,
+}
+
+/// The atlas texture holding every material tile, plus what the shader needs
+/// to find a layer in it.
+pub struct TextureAtlas {
+    pub texture: TextureId,
+    /// Tiles per row and per column.
+    pub grid: [f32; 2],
+    /// Gutter width relative to a whole atlas cell.
+    pub padding: f32,
+}
+
+impl MaterialRegistry {
+    pub fn load(path: &str) -> MaterialRegistry {
+        let json = std::fs::read_to_string(path).expect("Failed to read material manifest");
+        let base_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+        MaterialRegistry::from_json(&json, base_dir).expect("Failed to parse material manifest")
+    }
+
+    /// Parses a manifest. Texture paths are relative to `base_dir`.
+    pub fn from_json(json: &str, base_dir: &Path) -> Result<MaterialRegistry, String> {
+        let value: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let tile_size = value
+            .get("tile_size")
+            .and_then(Value::as_u64)
+            .ok_or("Manifest needs a number \"tile_size\"")? as u32;
+        let entries = value
+            .get("materials")
+            .and_then(Value::as_array)
+            .ok_or("Manifest needs a \"materials\" array")?
+            .iter()
+            .map(|material| {
+                let name = material
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or("Material is missing \"name\"")?;
+                let texture = material
+                    .get("texture")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| format!("Material \"{}\" is missing \"texture\"", name))?;
+                Ok(MaterialEntry {
+                    name: name.to_string(),
+                    texture_path: base_dir.join(texture).to_string_lossy().into_owned(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if entries.is_empty() {
+            return Err("Manifest has no materials".to_string());
+        }
+        Ok(MaterialRegistry { tile_size, entries })
+    }
+
+    /// Atlas layer of the material called `name`.
+    pub fn layer(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    /// Loads every material texture into one mipmapped atlas. Tiles are laid
+    /// out in registry order, left to right and top to bottom.
+    pub fn build_atlas(&self, ctx: &mut dyn RenderingBackend) -> TextureAtlas {
+        let tile = self.tile_size;
+        let gutter = tile / GUTTER_FRACTION;
+        let cell = tile + 2 * gutter;
+        let columns = (self.entries.len() as f32).sqrt().ceil() as u32;
+        let rows = (self.entries.len() as u32).div_ceil(columns);
+
+        let mut atlas: RgbaImage = ImageBuffer::new(columns * cell, rows * cell);
+        for (layer, entry) in self.entries.iter().enumerate() {
+            let (bytes, width, height) = load_image_bytes(&entry.texture_path);
+            let image: RgbaImage = ImageBuffer::from_raw(width, height, bytes)
+                .expect("Texture has an unexpected size");
+            let image = imageops::resize(&image, tile, tile, imageops::FilterType::Triangle);
+
+            let cell_x = layer as u32 % columns * cell;
+            let cell_y = layer as u32 / columns * cell;
+            // Fill the gutter with the opposite edge of the tile, since textures repeat
+            for y in 0..cell {
+                for x in 0..cell {
+                    let source_x = (x + tile - gutter) % tile;
+                    let source_y = (y + tile - gutter) % tile;
+                    let pixel: Rgba<u8> = *image.get_pixel(source_x, source_y);
+                    atlas.put_pixel(cell_x + x, cell_y + y, pixel);
+                }
+            }
+        }
+
+        let texture = ctx.new_texture(
+            TextureAccess::Static,
+            TextureSource::Bytes(atlas.as_raw()),
+            TextureParams {
+                width: atlas.width(),
+                height: atlas.height(),
+                format: TextureFormat::RGBA8,
+                wrap: TextureWrap::Clamp,
+                kind: TextureKind::Texture2D,
+                min_filter: FilterMode::Linear,
+                mag_filter: FilterMode::Linear,
+                mipmap_filter: MipmapFilterMode::Linear,
+                allocate_mipmaps: true,
+                sample_count: 1,
+            },
+        );
+        ctx.texture_generate_mipmaps(texture);
+
+        TextureAtlas {
+            texture,
+            grid: [columns as f32, rows as f32],
+            padding: gutter as f32 / cell as f32,
+        }
+    }
+}
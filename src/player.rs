@@ -0,0 +1,431 @@
+use crate::chunk::THRESHOLD;
+use crate::raycast::{raycast, IsoSurface};
+use glam::Vec3;
+
+const CAPSULE_RADIUS: f32 = 0.4;
+const CAPSULE_HEIGHT: f32 = 1.8;
+/// Height of the camera above the player's feet.
+pub const EYE_HEIGHT: f32 = 1.6;
+
+/// Walking speed in units per second.
+const WALK_SPEED: f32 = 6.0;
+const GRAVITY: f32 = 30.0;
+const JUMP_SPEED: f32 = 9.0;
+/// Fastest the player can fall, in units per second.
+const MAX_FALL_SPEED: f32 = 50.0;
+/// Highest ledge the player walks up without jumping.
+const STEP_HEIGHT: f32 = 0.6;
+/// Steepest slope the player can stand on and walk up.
+const MAX_SLOPE_DEGREES: f32 = 50.0;
+/// Speed at which the player slides down slopes that are too steep.
+const SLIDE_SPEED: f32 = 4.0;
+
+/// Longest distance moved before checking for collisions again, so fast moves
+/// don't pass through thin terrain.
+const MAX_SUBSTEP: f32 = 0.2;
+/// Resolution of the step-up and ground searches.
+const SEARCH_STEP: f32 = 0.05;
+
+/// What the player wants to do during an update.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MoveInput {
+    /// Horizontal walking direction, zero when standing still.
+    pub direction: Vec3,
+    pub jump: bool,
+}
+
+/// Walking player, colliding with the terrain as an upright capsule.
+pub struct Player {
+    /// Position of the bottom of the capsule.
+    pub position: Vec3,
+    velocity: Vec3,
+    /// Flies freely through the terrain instead of walking.
+    pub noclip: bool,
+}
+
+impl Player {
+    pub fn new(eye_position: Vec3) -> Player {
+        Player {
+            position: eye_position - Vec3::Y * EYE_HEIGHT,
+            velocity: Vec3::ZERO,
+            noclip: false,
+        }
+    }
+
+    pub fn eye_position(&self) -> Vec3 {
+        self.position + Vec3::Y * EYE_HEIGHT
+    }
+
+    /// Moves the player to a camera position, used while flying in noclip.
+    pub fn follow_camera(&mut self, eye_position: Vec3) {
+        self.position = eye_position - Vec3::Y * EYE_HEIGHT;
+        self.velocity = Vec3::ZERO;
+    }
+
+    /// Advances the player by `dt` seconds.
+    pub fn update(&mut self, dt: f32, input: MoveInput, terrain: &impl IsoSurface) {
+        // Wait for the terrain around the player to load instead of falling through it
+        if terrain.density(self.position).is_none() {
+            return;
+        }
+
+        // Terrain edits or spawning can leave the player inside the ground
+        if self.collides(self.position, terrain) {
+            self.position.y += STEP_HEIGHT;
+            self.velocity.y = 0.0;
+            return;
+        }
+
+        let ground_normal = self.ground_normal(terrain);
+        let on_ground = ground_normal.is_some() && self.velocity.y <= 0.0;
+        let walkable =
+            ground_normal.is_some_and(|normal| normal.y >= MAX_SLOPE_DEGREES.to_radians().cos());
+
+        let mut horizontal = input.direction.normalize_or_zero() * WALK_SPEED;
+        if on_ground && !walkable {
+            // Too steep to stand on: slide down and away from the slope
+            let normal = ground_normal.unwrap_or(Vec3::Y);
+            horizontal += Vec3::new(normal.x, 0.0, normal.z).normalize_or_zero() * SLIDE_SPEED;
+        }
+
+        if on_ground && walkable {
+            self.velocity.y = if input.jump { JUMP_SPEED } else { 0.0 };
+        } else {
+            self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-MAX_FALL_SPEED);
+        }
+
+        self.move_horizontal(horizontal * dt, walkable, terrain);
+        self.move_vertical(self.velocity.y * dt, terrain);
+
+        // Stay on the ground when walking down slopes and steps
+        if walkable && self.velocity.y <= 0.0 {
+            self.snap_to_ground(terrain);
+        }
+    }
+
+    fn move_horizontal(&mut self, delta: Vec3, can_step: bool, terrain: &impl IsoSurface) {
+        let substeps = (delta.length() / MAX_SUBSTEP).ceil().max(1.0) as usize;
+        let step = delta / substeps as f32;
+
+        for _ in 0..substeps {
+            let target = self.position + step;
+            if !self.collides(target, terrain) {
+                self.position = target;
+                continue;
+            }
+
+            if can_step {
+                let lifts = (STEP_HEIGHT / SEARCH_STEP) as usize;
+                if let Some(lifted) = (1..=lifts)
+                    .map(|lift| target + Vec3::Y * (lift as f32 * SEARCH_STEP))
+                    .find(|&lifted| !self.collides(lifted, terrain))
+                {
+                    self.position = lifted;
+                    continue;
+                }
+            }
+
+            // Slide along whichever axis is still free
+            let slides = [Vec3::new(step.x, 0.0, 0.0), Vec3::new(0.0, 0.0, step.z)];
+            match slides
+                .into_iter()
+                .map(|slide| self.position + slide)
+                .find(|&slid| !self.collides(slid, terrain))
+            {
+                Some(slid) => self.position = slid,
+                None => break,
+            }
+        }
+    }
+
+    fn move_vertical(&mut self, delta: f32, terrain: &impl IsoSurface) {
+        let substeps = (delta.abs() / MAX_SUBSTEP).ceil().max(1.0) as usize;
+        let step = delta / substeps as f32;
+
+        for _ in 0..substeps {
+            let target = self.position + Vec3::Y * step;
+            if self.collides(target, terrain) {
+                // Landed or hit the head; get as close as the search allows
+                let searches = (step.abs() / SEARCH_STEP) as usize;
+                if let Some(closest) = (1..=searches)
+                    .rev()
+                    .map(|search| {
+                        self.position + Vec3::Y * (step.signum() * search as f32 * SEARCH_STEP)
+                    })
+                    .find(|&closer| !self.collides(closer, terrain))
+                {
+                    self.position = closest;
+                }
+                self.velocity.y = 0.0;
+                return;
+            }
+            self.position = target;
+        }
+    }
+
+    fn snap_to_ground(&mut self, terrain: &impl IsoSurface) {
+        let searches = (STEP_HEIGHT / SEARCH_STEP) as usize;
+        let mut lowest = self.position;
+        for search in 1..=searches {
+            let lower = self.position - Vec3::Y * (search as f32 * SEARCH_STEP);
+            if self.collides(lower, terrain) {
+                self.position = lowest;
+                return;
+            }
+            lowest = lower;
+        }
+        // Nothing within a step below: the player walked off a ledge and falls
+    }
+
+    /// Normal of the terrain right below the feet, if the player stands on it.
+    /// A player resting on an edge, with nothing right below the feet, counts
+    /// as standing on flat ground.
+    fn ground_normal(&self, terrain: &impl IsoSurface) -> Option<Vec3> {
+        raycast(
+            terrain,
+            self.position + Vec3::Y * SEARCH_STEP,
+            -Vec3::Y,
+            SEARCH_STEP * 3.0,
+            THRESHOLD,
+        )
+        .map(|hit| hit.normal)
+        .or_else(|| {
+            self.collides(self.position - Vec3::Y * SEARCH_STEP, terrain)
+                .then_some(Vec3::Y)
+        })
+    }
+
+    /// Whether the capsule with its bottom at `position` overlaps the terrain,
+    /// going by points on its surface.
+    fn collides(&self, position: Vec3, terrain: &impl IsoSurface) -> bool {
+        // Rings around the bottom hemisphere, the middle and the top hemisphere
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        let rings = [
+            (CAPSULE_RADIUS * (1.0 - diagonal), CAPSULE_RADIUS * diagonal),
+            (CAPSULE_RADIUS, CAPSULE_RADIUS),
+            (CAPSULE_HEIGHT / 2.0, CAPSULE_RADIUS),
+            (CAPSULE_HEIGHT - CAPSULE_RADIUS, CAPSULE_RADIUS),
+        ];
+
+        let ring_points = rings.into_iter().flat_map(|(height, radius)| {
+            (0..8).map(move |i| {
+                let angle = i as f32 * std::f32::consts::FRAC_PI_4;
+                position + Vec3::new(angle.cos() * radius, height, angle.sin() * radius)
+            })
+        });
+        [position, position + Vec3::Y * CAPSULE_HEIGHT]
+            .into_iter()
+            .chain(ring_points)
+            .any(|point| {
+                terrain
+                    .density(point)
+                    .is_some_and(|density| density >= THRESHOLD)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::input::{Action, Binding, Input, KEYMAP_PATH};
+    use crate::material::Material;
+    use crate::timestep::FixedTimestep;
+    use miniquad::KeyCode;
+    use std::time::Duration;
+
+    /// Ticks per second, with a tick length exact in binary like every frame
+    /// length below, so all pacings add up to the same ticks.
+    const TICK_RATE: f64 = 64.0;
+
+    /// Flat ground with its surface at `height`.
+    struct Ground {
+        height: f32,
+    }
+
+    impl IsoSurface for Ground {
+        fn density(&self, position: Vec3) -> Option<f32> {
+            Some(THRESHOLD + self.height - position.y)
+        }
+
+        fn material(&self, _: Vec3) -> Option<Material> {
+            Some(Material::Grass)
+        }
+    }
+
+    /// Walks and jumps over flat ground for frames of the given lengths, in
+    /// 128ths of a second, the way `Stage` ticks the player and camera.
+    /// Returns where the player ends up and how fast it is moving.
+    fn walk(frames: impl IntoIterator<Item = u32>) -> (Vec3, Vec3) {
+        let ground = Ground { height: 2.0 };
+        let mut input = Input::load(KEYMAP_PATH, None);
+        input.press(Binding::Key(KeyCode::W));
+        input.press(Binding::Key(KeyCode::Space));
+
+        let mut camera = Camera::new();
+        camera.process_mouse(100.0, -20.0);
+        camera.position = Vec3::new(3.0, 5.0, -4.0);
+        let mut player = Player::new(camera.position);
+        let mut timestep = FixedTimestep::new(TICK_RATE);
+
+        for frame in frames {
+            let elapsed = Duration::from_secs_f64(frame as f64 / 128.0);
+            for _ in 0..timestep.advance(elapsed) {
+                let input = MoveInput {
+                    direction: camera.walk_direction(&input),
+                    jump: input.is_held(Action::Jump),
+                };
+                player.update(timestep.tick_duration(), input, &ground);
+                camera.position = player.eye_position();
+            }
+        }
+        (player.position, player.velocity)
+    }
+
+    #[test]
+    fn frame_pacing_does_not_change_the_simulation() {
+        // 2 1/32 seconds in every case
+        let steady = walk(vec![2; 130]);
+        let fast = walk(vec![1; 260]);
+        let uneven = walk([1, 3, 8, 16, 4].repeat(8).into_iter().chain([4]));
+
+        assert_eq!(steady, fast);
+        assert_eq!(steady, uneven);
+
+        let (position, _) = steady;
+        assert!(
+            Vec3::new(position.x - 3.0, 0.0, position.z + 4.0).length() > 5.0,
+            "the player should have walked"
+        );
+    }
+
+    /// Flat ground at height 0, raised to `height` from `edge` on along x.
+    struct Step {
+        edge: f32,
+        height: f32,
+    }
+
+    impl IsoSurface for Step {
+        fn density(&self, position: Vec3) -> Option<f32> {
+            let ground = if position.x >= self.edge {
+                self.height
+            } else {
+                0.0
+            };
+            Some(THRESHOLD + ground - position.y)
+        }
+
+        fn material(&self, _: Vec3) -> Option<Material> {
+            Some(Material::Stone)
+        }
+    }
+
+    /// Flat ground at height 0 with a solid wall filling everything from `x` on.
+    struct Wall {
+        x: f32,
+    }
+
+    impl IsoSurface for Wall {
+        fn density(&self, position: Vec3) -> Option<f32> {
+            Some(THRESHOLD + (-position.y).max(position.x - self.x))
+        }
+
+        fn material(&self, _: Vec3) -> Option<Material> {
+            Some(Material::Stone)
+        }
+    }
+
+    /// Runs the player for `seconds` at `TICK_RATE`, walking in `direction`.
+    fn run(player: &mut Player, terrain: &impl IsoSurface, direction: Vec3, seconds: f32) {
+        let dt = 1.0 / TICK_RATE as f32;
+        let input = MoveInput {
+            direction,
+            jump: false,
+        };
+        for _ in 0..(seconds * TICK_RATE as f32) as usize {
+            player.update(dt, input, terrain);
+        }
+    }
+
+    /// Player standing with its feet at `position`.
+    fn standing_at(position: Vec3) -> Player {
+        Player::new(position + Vec3::Y * EYE_HEIGHT)
+    }
+
+    #[test]
+    fn rests_on_flat_ground() {
+        let ground = Ground { height: 0.0 };
+        let mut player = standing_at(Vec3::new(1.0, 4.0, 1.0));
+        run(&mut player, &ground, Vec3::ZERO, 2.0);
+
+        assert!(
+            player.position.y.abs() <= SEARCH_STEP,
+            "{}",
+            player.position
+        );
+        assert_eq!(player.velocity, Vec3::ZERO);
+
+        // And stays put
+        let rested = player.position;
+        run(&mut player, &ground, Vec3::ZERO, 1.0);
+        assert_eq!(player.position, rested);
+    }
+
+    #[test]
+    fn climbs_steps_lower_than_the_step_height() {
+        let step = Step {
+            edge: 2.0,
+            height: STEP_HEIGHT - 0.2,
+        };
+        let mut player = standing_at(Vec3::ZERO);
+        run(&mut player, &step, Vec3::X, 1.0);
+
+        assert!(player.position.x > 4.0, "{}", player.position);
+        assert!(
+            (player.position.y - step.height).abs() <= SEARCH_STEP,
+            "{}",
+            player.position
+        );
+    }
+
+    #[test]
+    fn snaps_down_steps_instead_of_falling() {
+        let step = Step {
+            edge: 2.0,
+            height: STEP_HEIGHT - 0.2,
+        };
+        let mut player = standing_at(Vec3::new(4.0, step.height, 0.0));
+        run(&mut player, &step, Vec3::NEG_X, 0.5);
+
+        assert!(player.position.x < 1.5, "{}", player.position);
+        assert!(
+            player.position.y.abs() <= SEARCH_STEP,
+            "{}",
+            player.position
+        );
+        assert_eq!(player.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn is_blocked_by_walls() {
+        let wall = Wall { x: 2.0 };
+        let mut player = standing_at(Vec3::ZERO);
+        run(&mut player, &wall, Vec3::X, 1.0);
+
+        assert!(
+            player.position.x <= wall.x - CAPSULE_RADIUS,
+            "{}",
+            player.position
+        );
+        assert!(
+            player.position.x > wall.x - CAPSULE_RADIUS - MAX_SUBSTEP,
+            "{}",
+            player.position
+        );
+        assert!(
+            player.position.y.abs() <= SEARCH_STEP,
+            "{}",
+            player.position
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::material::Material;
+use glam::Vec3;
+
+/// Distance between density samples while marching along a ray. Surface
+/// features thinner than this can be missed.
+const RAY_STEP: f32 = 0.25;
+/// Bisection steps used to refine the crossing point once it has been bracketed.
+const REFINE_STEPS: usize = 10;
+/// Offset of the samples used for the surface normal.
+const NORMAL_EPSILON: f32 = 0.5;
+
+/// A density field whose isosurface rays can be cast against.
+pub trait IsoSurface {
+    /// Density at a world position, or `None` where the field isn't available.
+    fn density(&self, position: Vec3) -> Option<f32>;
+    /// Material of the solid at a world position.
+    fn material(&self, position: Vec3) -> Option<Material>;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    /// Point on the isosurface where the ray enters the solid.
+    pub position: Vec3,
+    /// Unit surface normal pointing out of the solid.
+    pub normal: Vec3,
+    /// Distance from the ray origin to `position`.
+    pub distance: f32,
+    pub material: Material,
+}
+
+/// Finds the first point along a ray where the density reaches `threshold`.
+///
+/// The ray is marched in fixed steps and the crossing is refined by bisection.
+/// Rays that start inside the solid, or that leave the available field before
+/// hitting anything, return `None`.
+pub fn raycast(
+    surface: &impl IsoSurface,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    threshold: f32,
+) -> Option<RayHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO || surface.density(origin)? >= threshold {
+        return None;
+    }
+
+    let steps = (max_distance / RAY_STEP).ceil() as usize;
+    let mut previous = 0.0;
+    for step in 1..=steps {
+        let distance = (step as f32 * RAY_STEP).min(max_distance);
+        if surface.density(origin + direction * distance)? < threshold {
+            previous = distance;
+            continue;
+        }
+
+        // The surface lies between the last sample in air and this one
+        let (mut air, mut solid) = (previous, distance);
+        for _ in 0..REFINE_STEPS {
+            let middle = (air + solid) * 0.5;
+            if surface.density(origin + direction * middle)? < threshold {
+                air = middle;
+            } else {
+                solid = middle;
+            }
+        }
+
+        let position = origin + direction * solid;
+        let normal = surface_normal(surface, position).unwrap_or(-direction);
+        // Look up the material just inside the solid, so rounding to the nearest
+        // sample doesn't land in the air
+        let material = surface
+            .material(position - normal * NORMAL_EPSILON)
+            .or_else(|| surface.material(position))?;
+        return Some(RayHit {
+            position,
+            normal,
+            distance: solid,
+            material,
+        });
+    }
+    None
+}
+
+/// Negated density gradient from central differences, or `None` when it vanishes
+/// or a sample is unavailable.
+fn surface_normal(surface: &impl IsoSurface, position: Vec3) -> Option<Vec3> {
+    let mut gradient = Vec3::ZERO;
+    for axis in 0..3 {
+        let mut offset = Vec3::ZERO;
+        offset[axis] = NORMAL_EPSILON;
+        gradient[axis] =
+            surface.density(position + offset)? - surface.density(position - offset)?;
+    }
+    (-gradient).try_normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 1e-3;
+
+    /// Solid below `height`, grass within a unit of the surface and stone deeper.
+    struct Plane {
+        height: f32,
+    }
+
+    impl IsoSurface for Plane {
+        fn density(&self, position: Vec3) -> Option<f32> {
+            Some(self.height - position.y)
+        }
+
+        fn material(&self, position: Vec3) -> Option<Material> {
+            Some(if position.y > self.height - 1.0 {
+                Material::Grass
+            } else {
+                Material::Stone
+            })
+        }
+    }
+
+    /// Solid ore ball.
+    struct Sphere {
+        center: Vec3,
+        radius: f32,
+    }
+
+    impl IsoSurface for Sphere {
+        fn density(&self, position: Vec3) -> Option<f32> {
+            Some(self.radius - position.distance(self.center))
+        }
+
+        fn material(&self, _: Vec3) -> Option<Material> {
+            Some(Material::Ore)
+        }
+    }
+
+    fn assert_close(actual: Vec3, expected: Vec3) {
+        assert!(
+            actual.abs_diff_eq(expected, TOLERANCE),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn hits_a_plane() {
+        let plane = Plane { height: 2.0 };
+        let origin = Vec3::new(1.0, 10.0, -3.0);
+        let direction = Vec3::new(0.6, -0.8, 0.0);
+
+        let hit = raycast(&plane, origin, direction, 100.0, 0.0).unwrap();
+        assert_close(hit.position, Vec3::new(7.0, 2.0, -3.0));
+        assert_close(hit.normal, Vec3::Y);
+        assert!((hit.distance - 10.0).abs() < TOLERANCE);
+        assert_eq!(hit.material, Material::Grass);
+    }
+
+    #[test]
+    fn hits_a_sphere() {
+        let sphere = Sphere {
+            center: Vec3::new(0.0, 5.0, 0.0),
+            radius: 3.0,
+        };
+        let origin = Vec3::new(-10.0, 5.0, 0.0);
+
+        let hit = raycast(&sphere, origin, Vec3::X * 2.0, 100.0, 0.0).unwrap();
+        assert_close(hit.position, Vec3::new(-3.0, 5.0, 0.0));
+        assert_close(hit.normal, Vec3::NEG_X);
+        assert!((hit.distance - 7.0).abs() < TOLERANCE);
+        assert_eq!(hit.material, Material::Ore);
+
+        // Passes just outside the ball
+        let above = origin + Vec3::Y * 3.1;
+        assert!(raycast(&sphere, above, Vec3::X, 100.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn misses_beyond_max_distance() {
+        let plane = Plane { height: 2.0 };
+        let origin = Vec3::new(0.0, 10.0, 0.0);
+        assert!(raycast(&plane, origin, Vec3::NEG_Y, 7.9, 0.0).is_none());
+        assert!(raycast(&plane, origin, Vec3::NEG_Y, 8.1, 0.0).is_some());
+    }
+
+    #[test]
+    fn ignores_rays_starting_inside_the_solid() {
+        let plane = Plane { height: 2.0 };
+        let origin = Vec3::new(0.0, 1.0, 0.0);
+        assert!(raycast(&plane, origin, Vec3::Y, 100.0, 0.0).is_none());
+        assert!(raycast(&plane, origin, Vec3::NEG_Y, 100.0, 0.0).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::biome::Biome;
+use crate::material::Material;
+use glam::{IVec3, Vec3};
+use rayon::prelude::*;
+
+/// A 3D grid of density samples stored in one contiguous buffer.
+///
+/// Samples are laid out x-major, then y, then z, matching the order the mesher
+/// walks them in, and sample `(x, y, z)` lies at world position
+/// `origin + (x, y, z) * step`. Every sample also stores its material, and every
+/// `(x, z)` column its biome.
+#[derive(Clone)]
+pub struct ScalarField {
+    dimensions: [usize; 3],
+    origin: IVec3,
+    /// World units between neighbouring samples; above 1 for coarser levels of detail.
+    step: i32,
+    data: Vec<f32>,
+    materials: Vec<Material>,
+    biomes: Vec<Biome>,
+}
+
+impl ScalarField {
+    pub fn from_data(
+        dimensions: [usize; 3],
+        origin: IVec3,
+        step: i32,
+        data: Vec<f32>,
+        biomes: Vec<Biome>,
+    ) -> ScalarField {
+        assert_eq!(data.len(), dimensions[0] * dimensions[1] * dimensions[2]);
+        assert_eq!(biomes.len(), dimensions[0] * dimensions[2]);
+        ScalarField {
+            dimensions,
+            origin,
+            step,
+            materials: vec![Material::Stone; data.len()],
+            data,
+            biomes,
+        }
+    }
+
+    pub fn dimensions(&self) -> [usize; 3] {
+        self.dimensions
+    }
+
+    pub fn origin(&self) -> IVec3 {
+        self.origin
+    }
+
+    pub fn step(&self) -> i32 {
+        self.step
+    }
+
+    /// World position of a sample.
+    pub fn world_position(&self, [x, y, z]: [usize; 3]) -> IVec3 {
+        self.origin + IVec3::new(x as i32, y as i32, z as i32) * self.step
+    }
+
+    /// Total number of samples.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.data[self.index(x, y, z)]
+    }
+
+    #[inline]
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: f32) {
+        let index = self.index(x, y, z);
+        self.data[index] = value;
+    }
+
+    #[inline]
+    pub fn material(&self, x: usize, y: usize, z: usize) -> Material {
+        self.materials[self.index(x, y, z)]
+    }
+
+    pub fn set_material(&mut self, x: usize, y: usize, z: usize, material: Material) {
+        let index = self.index(x, y, z);
+        self.materials[index] = material;
+    }
+
+    pub fn set_materials(&mut self, materials: Vec<Material>) {
+        assert_eq!(materials.len(), self.data.len());
+        self.materials = materials;
+    }
+
+    #[inline]
+    pub fn biome(&self, x: usize, z: usize) -> Biome {
+        self.biomes[x * self.dimensions[2] + z]
+    }
+
+    /// Central-difference gradient, falling back to one-sided differences on
+    /// the border of the field.
+    pub fn gradient(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        let [size_x, size_y, size_z] = self.dimensions;
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(size_x - 1));
+        let (y0, y1) = (y.saturating_sub(1), (y + 1).min(size_y - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(size_z - 1));
+        Vec3::new(
+            (self.get(x1, y, z) - self.get(x0, y, z)) / (x1 - x0) as f32,
+            (self.get(x, y1, z) - self.get(x, y0, z)) / (y1 - y0) as f32,
+            (self.get(x, y, z1) - self.get(x, y, z0)) / (z1 - z0) as f32,
+        ) / self.step as f32
+    }
+
+    /// Replaces every sample with `f(index, world_position, value)`, in parallel.
+    pub fn update_parallel(&mut self, f: impl Fn(usize, IVec3, f32) -> f32 + Sync) {
+        let [_, size_y, size_z] = self.dimensions;
+        let (origin, step) = (self.origin, self.step);
+        self.data
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(index, value)| {
+                let x = index / (size_y * size_z);
+                let y = index / size_z % size_y;
+                let z = index % size_z;
+                let world = origin + IVec3::new(x as i32, y as i32, z as i32) * step;
+                *value = f(index, world, *value);
+            });
+    }
+
+    /// Inverse of [`ScalarField::index`].
+    #[inline]
+    pub fn sample_position(&self, index: usize) -> [usize; 3] {
+        let [_, size_y, size_z] = self.dimensions;
+        [
+            index / (size_y * size_z),
+            index / size_z % size_y,
+            index % size_z,
+        ]
+    }
+
+    #[inline]
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (x * self.dimensions[1] + y) * self.dimensions[2] + z
+    }
+}
@@ -1,34 +1,77 @@
-use noise::{Perlin, NoiseFn};
+use crate::biome::ClimateMap;
+use crate::scalar_field::ScalarField;
+use crate::world_config::WorldGenConfig;
+use glam::{DVec3, IVec3};
 use rayon::prelude::*;
 
-pub fn generate_scalar_field(grid_size: usize, position: Vec<i32>) -> Vec<Vec<Vec<f32>>> {
-    let noise = Perlin::new(1024);
-
-    let global_scale = 1.2;
-    let scale = 0.01 * global_scale;
-    let falloff = 0.01 * global_scale;
-
-    let field: Vec<Vec<Vec<f32>>> = (0..grid_size)
-        .into_par_iter()
-        .map(|x| {
-            (0..grid_size)
-                .map(|y| {
-                    (0..grid_size)
-                        .map(|z| {
-                            let noise_value = noise.get([
-                                (x as f64 + position[0] as f64) * scale,
-                                (y as f64 + position[1] as f64) * scale,
-                                (z as f64 + position[2] as f64) * scale,
-                            ]);
-
-                            let final_value = noise_value - ((y as f64 - 150.0 * global_scale) * falloff);
-                            final_value as f32
-                        })
-                        .collect()
-                })
-                .collect()
+pub fn generate_scalar_field(
+    config: &WorldGenConfig,
+    dimensions: [usize; 3],
+    origin: IVec3,
+    step: i32,
+) -> ScalarField {
+    let density = config.density_function().compile(config.seed);
+
+    let [_, size_y, size_z] = dimensions;
+    let mut data = vec![0.0; dimensions[0] * size_y * size_z];
+
+    data.par_chunks_mut(size_y * size_z)
+        .enumerate()
+        .for_each(|(x, slab)| {
+            for y in 0..size_y {
+                for z in 0..size_z {
+                    let world_x = (x as i32 * step + origin.x) as f64;
+                    let world_y = (y as i32 * step + origin.y) as f64;
+                    let world_z = (z as i32 * step + origin.z) as f64;
+
+                    let value = density(DVec3::new(world_x, world_y, world_z));
+                    slab[y * size_z + z] = value as f32;
+                }
+            }
+        });
+
+    let climate = ClimateMap::new(config.seed);
+    let biomes = (0..dimensions[0] * size_z)
+        .map(|column| {
+            let world_x = (column / size_z) as i32 * step + origin.x;
+            let world_z = (column % size_z) as i32 * step + origin.z;
+            climate.biome(world_x as f64, world_z as f64)
         })
         .collect();
 
-    field
+    ScalarField::from_data(dimensions, origin, step, data, biomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_bits(config: &WorldGenConfig) -> Vec<u32> {
+        let field = generate_scalar_field(config, [12; 3], IVec3::new(-6, 96, 20), 2);
+        (0..field.len())
+            .map(|index| {
+                let [x, y, z] = field.sample_position(index);
+                field.get(x, y, z).to_bits()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_identical_fields() {
+        let config = WorldGenConfig {
+            seed: 7,
+            ..WorldGenConfig::default()
+        };
+        assert_eq!(field_bits(&config), field_bits(&config.clone()));
+    }
+
+    #[test]
+    fn different_seeds_give_different_fields() {
+        let config = WorldGenConfig::default();
+        let other = WorldGenConfig {
+            seed: config.seed + 1,
+            ..config.clone()
+        };
+        assert_ne!(field_bits(&config), field_bits(&other));
+    }
 }
@@ -1,4 +1,31 @@
+use crate::building::PIECE_MATERIAL_COUNT;
+use crate::material::MATERIAL_COUNT;
+
 #[repr(C)]
 pub struct UniformsDefault {
     pub mvp: [[f32; 4]; 4],
-}
\ No newline at end of file
+    pub sun_direction: [f32; 3],
+    /// Texture repeats per world unit.
+    pub texture_scale: f32,
+    /// 1 to texture with the stored texture coordinates instead of triplanarly.
+    pub use_tex_coords: f32,
+    /// Tiles per row and per column of the material atlas.
+    pub atlas_grid: [f32; 2],
+    pub atlas_padding: f32,
+    /// Atlas layer of every terrain material id.
+    pub material_layers: [f32; MATERIAL_COUNT],
+}
+
+#[repr(C)]
+pub struct UniformsBuilding {
+    pub mvp: [[f32; 4]; 4],
+    pub sun_direction: [f32; 3],
+    /// Texture repeats per world unit.
+    pub texture_scale: f32,
+    pub atlas_grid: [f32; 2],
+    pub atlas_padding: f32,
+    /// Colour multiplied into every fragment, used to draw the ghost preview.
+    pub tint: [f32; 4],
+    /// Atlas layer of every piece material id.
+    pub material_layers: [f32; PIECE_MATERIAL_COUNT],
+}
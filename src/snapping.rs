@@ -0,0 +1,258 @@
+use crate::building::{Piece, PieceKind, Placement, PIECE_SIZE};
+use glam::{Vec3, Vec3Swizzles};
+
+/// What the placement ray hit.
+#[derive(Clone, Copy, Debug)]
+pub struct SnapTarget {
+    pub position: Vec3,
+    pub normal: Vec3,
+    /// The building piece that was hit, or `None` for the terrain.
+    pub piece: Option<Piece>,
+}
+
+/// Building grid, anchored at the bottom centre of the first foundation. Floors
+/// sit on cell centres, walls on cell edges and pillars on cell corners, with a
+/// storey every `PIECE_SIZE` units.
+#[derive(Clone, Copy, Debug)]
+pub struct BuildGrid {
+    origin: Vec3,
+}
+
+impl BuildGrid {
+    pub fn new(origin: Vec3) -> BuildGrid {
+        BuildGrid { origin }
+    }
+
+    /// Where the piece goes when aiming at `target`. Pieces aimed at connect
+    /// through their sockets; anything else snaps to the nearest grid slot.
+    pub fn snap(&self, placement: &Placement, target: &SnapTarget) -> Piece {
+        target
+            .piece
+            .and_then(|piece| snap_to_socket(placement, target, &piece))
+            .unwrap_or_else(|| self.snap_to_grid(placement, target.position))
+    }
+
+    fn snap_to_grid(&self, placement: &Placement, position: Vec3) -> Piece {
+        let relative = (position - self.origin) / PIECE_SIZE;
+        let centre = |value: f32| value.round();
+        let edge = |value: f32| (value - 0.5).round() + 0.5;
+
+        let (x, z) = match placement.kind {
+            PieceKind::Floor | PieceKind::Ramp => (centre(relative.x), centre(relative.z)),
+            PieceKind::Pillar => (edge(relative.x), edge(relative.z)),
+            // Walls span x when unrotated
+            _ if placement.rotation.is_multiple_of(2) => (centre(relative.x), edge(relative.z)),
+            _ => (edge(relative.x), centre(relative.z)),
+        };
+
+        placement.piece_at(self.origin + Vec3::new(x, relative.y.round(), z) * PIECE_SIZE)
+    }
+}
+
+/// Connects a piece to the piece being aimed at:
+/// - walls on the nearest edge of a floor, pillars on its nearest corner and
+///   floors next to it
+/// - floors on top of a wall, on the side it is aimed at from
+/// - roofs (ramps) on top of a wall, rising away from it
+/// - walls stacked on walls
+fn snap_to_socket(placement: &Placement, target: &SnapTarget, piece: &Piece) -> Option<Piece> {
+    let half = PIECE_SIZE / 2.0;
+    let offset = target.position - piece.position;
+
+    match piece.kind {
+        PieceKind::Floor => {
+            // Nearest edge of the floor, as a unit step along x or z
+            let step = if offset.x.abs() > offset.z.abs() {
+                Vec3::new(offset.x.signum(), 0.0, 0.0)
+            } else {
+                Vec3::new(0.0, 0.0, offset.z.signum())
+            };
+
+            match placement.kind {
+                PieceKind::Floor => Some(Piece {
+                    position: piece.position + step * PIECE_SIZE,
+                    ..placement.piece_at(piece.position)
+                }),
+                PieceKind::Pillar => Some(placement.piece_at(
+                    piece.position + Vec3::new(offset.x.signum(), 0.0, offset.z.signum()) * half,
+                )),
+                kind if kind.is_wall() => {
+                    // An edge along z needs a wall turned a quarter
+                    let parity = (step.x != 0.0) as u8;
+                    Some(Piece {
+                        rotation: with_parity(placement.rotation, parity),
+                        ..placement.piece_at(piece.position + step * half)
+                    })
+                }
+                _ => None,
+            }
+        }
+        kind if kind.is_wall() => {
+            let top = piece.position + Vec3::Y * PIECE_SIZE;
+            // Unit step across the wall, towards the side it is aimed at from
+            let across = if piece.rotation.is_multiple_of(2) {
+                Vec3::new(0.0, 0.0, side(target.normal.z, offset.z))
+            } else {
+                Vec3::new(side(target.normal.x, offset.x), 0.0, 0.0)
+            };
+
+            match placement.kind {
+                PieceKind::Floor => Some(placement.piece_at(top + across * half)),
+                PieceKind::Ramp => Some(Piece {
+                    rotation: facing_rotation(across),
+                    ..placement.piece_at(top + across * half)
+                }),
+                kind if kind.is_wall() => Some(Piece {
+                    rotation: piece.rotation,
+                    ..placement.piece_at(top)
+                }),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The rotation closest to `rotation` whose parity (span axis) is `parity`.
+fn with_parity(rotation: u8, parity: u8) -> u8 {
+    if rotation % 2 == parity {
+        rotation
+    } else {
+        (rotation + 1) % 4
+    }
+}
+
+/// Sign of the hit normal along an axis, falling back to the side of the hit
+/// point when the normal doesn't point across it.
+fn side(normal: f32, offset: f32) -> f32 {
+    if normal != 0.0 {
+        normal.signum()
+    } else {
+        offset.signum()
+    }
+}
+
+/// Quarter turns that point a ramp's rising direction (+z unrotated) along
+/// `direction`.
+fn facing_rotation(direction: Vec3) -> u8 {
+    let direction = direction.xz();
+    if direction.y > 0.5 {
+        0
+    } else if direction.x > 0.5 {
+        1
+    } else if direction.y < -0.5 {
+        2
+    } else {
+        3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::PieceMaterial;
+
+    fn placement(kind: PieceKind, rotation: u8) -> Placement {
+        Placement {
+            kind,
+            material: PieceMaterial::Wood,
+            rotation,
+        }
+    }
+
+    fn piece(kind: PieceKind, position: Vec3, rotation: u8) -> Piece {
+        placement(kind, rotation).piece_at(position)
+    }
+
+    /// Aims at `offset` from the piece's anchor, with the hit normal `normal`.
+    fn aim(piece: Piece, offset: Vec3, normal: Vec3) -> SnapTarget {
+        SnapTarget {
+            position: piece.position + offset,
+            normal,
+            piece: Some(piece),
+        }
+    }
+
+    #[test]
+    fn pieces_connect_to_the_nearest_side_of_a_floor() {
+        let grid = BuildGrid::new(Vec3::ZERO);
+        let floor = piece(PieceKind::Floor, Vec3::new(8.0, 4.0, 0.0), 0);
+        let east = aim(floor, Vec3::new(1.8, 0.1, 0.3), Vec3::Y);
+        let north = aim(floor, Vec3::new(0.3, 0.1, -1.5), Vec3::Y);
+
+        // Walls turn to run along the edge, keeping their rotation when they can
+        let wall = grid.snap(&placement(PieceKind::Wall, 0), &east);
+        assert_eq!(wall.position, Vec3::new(10.0, 4.0, 0.0));
+        assert_eq!(wall.rotation, 1);
+        let wall = grid.snap(&placement(PieceKind::Window, 3), &east);
+        assert_eq!(wall.rotation, 3);
+        let wall = grid.snap(&placement(PieceKind::DoorFrame, 3), &north);
+        assert_eq!(wall.position, Vec3::new(8.0, 4.0, -2.0));
+        assert_eq!(wall.rotation, 0);
+
+        let next = grid.snap(&placement(PieceKind::Floor, 2), &north);
+        assert_eq!(next.position, Vec3::new(8.0, 4.0, -4.0));
+        assert_eq!(next.rotation, 2);
+
+        let pillar = grid.snap(&placement(PieceKind::Pillar, 0), &north);
+        assert_eq!(pillar.position, Vec3::new(10.0, 4.0, -2.0));
+    }
+
+    #[test]
+    fn pieces_connect_to_the_top_of_a_wall() {
+        let grid = BuildGrid::new(Vec3::ZERO);
+        // Spanning x, aimed at from the -z side
+        let wall = piece(PieceKind::Wall, Vec3::new(0.0, 0.0, 2.0), 0);
+        let target = aim(wall, Vec3::new(0.5, 3.0, -0.1), Vec3::NEG_Z);
+
+        let floor = grid.snap(&placement(PieceKind::Floor, 0), &target);
+        assert_eq!(floor.position, Vec3::new(0.0, 4.0, 0.0));
+        let ramp = grid.snap(&placement(PieceKind::Ramp, 0), &target);
+        assert_eq!(ramp.position, Vec3::new(0.0, 4.0, 0.0));
+        assert_eq!(ramp.rotation, 2);
+        let stacked = grid.snap(&placement(PieceKind::Window, 1), &target);
+        assert_eq!(stacked.position, Vec3::new(0.0, 4.0, 2.0));
+        assert_eq!(stacked.rotation, 0);
+
+        // Spanning z, aimed at from the top, on its +x half
+        let wall = piece(PieceKind::Wall, Vec3::new(2.0, 0.0, 0.0), 1);
+        let target = aim(wall, Vec3::new(0.05, 4.0, 1.0), Vec3::Y);
+        let ramp = grid.snap(&placement(PieceKind::Ramp, 0), &target);
+        assert_eq!(ramp.position, Vec3::new(4.0, 4.0, 0.0));
+        assert_eq!(ramp.rotation, 1);
+    }
+
+    #[test]
+    fn unconnectable_pieces_snap_to_the_grid() {
+        let origin = Vec3::new(10.0, 5.0, -3.0);
+        let grid = BuildGrid::new(origin);
+        let position = origin + Vec3::new(5.1, 6.4, -6.2);
+        let terrain = SnapTarget {
+            position,
+            normal: Vec3::Y,
+            piece: None,
+        };
+        let snap = |kind, rotation| grid.snap(&placement(kind, rotation), &terrain).position;
+
+        // Floors on cell centres, walls on the edges they run along, pillars on
+        // corners, all on the nearest storey
+        assert_eq!(
+            snap(PieceKind::Floor, 0),
+            origin + Vec3::new(4.0, 8.0, -8.0)
+        );
+        assert_eq!(snap(PieceKind::Ramp, 3), origin + Vec3::new(4.0, 8.0, -8.0));
+        assert_eq!(snap(PieceKind::Wall, 0), origin + Vec3::new(4.0, 8.0, -6.0));
+        assert_eq!(snap(PieceKind::Wall, 2), origin + Vec3::new(4.0, 8.0, -6.0));
+        assert_eq!(snap(PieceKind::Wall, 1), origin + Vec3::new(6.0, 8.0, -8.0));
+        assert_eq!(
+            snap(PieceKind::Pillar, 0),
+            origin + Vec3::new(6.0, 8.0, -6.0)
+        );
+
+        // Pillars have no sockets
+        let pillar = piece(PieceKind::Pillar, position, 0);
+        let target = aim(pillar, Vec3::ZERO, Vec3::Y);
+        let floor = grid.snap(&placement(PieceKind::Floor, 0), &target);
+        assert_eq!(floor.position, origin + Vec3::new(4.0, 8.0, -8.0));
+    }
+}
@@ -1,78 +1,124 @@
+use crate::brush::{Brush, BrushMode, BrushShape, BrushStroke};
+use crate::building::{
+    Building, Piece, PieceHit, PieceKind, PieceMaterial, Placement, PIECE_MATERIAL_COUNT,
+    PIECE_SIZE,
+};
 use crate::camera::Camera;
-use crate::extras::load_image_bytes;
-use crate::marching_cubes::generate_marching_cubes;
-use crate::scalar_generator::generate_scalar_field;
+use crate::chunk::ChunkManager;
+use crate::frustum::Frustum;
+use crate::input::{Action, Binding, Input, KEYMAP_PATH, USER_KEYMAP_PATH};
+use crate::material::{Material, MATERIAL_COUNT};
+use crate::material_registry::{MaterialRegistry, TextureAtlas, MANIFEST_PATH};
+use crate::player::{MoveInput, Player};
+use crate::raycast::RayHit;
 use crate::shader;
+use crate::snapping::SnapTarget;
+use crate::structure::touches_ground;
+use crate::timestep::FixedTimestep;
+use crate::world_config::WorldGenConfig;
 use image::{ImageBuffer, Rgba};
 use miniquad::*;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use window::screen_size;
 
+/// Direction pointing from the terrain towards the sun.
+const SUN_DIRECTION: glam::Vec3 = glam::Vec3::new(0.4, 1.0, 0.3);
+
+/// Terrain texture repeats per world unit.
+const TEXTURE_SCALE: f32 = 1.0 / 16.0;
+
+/// Colour of the building ghost preview.
+const GHOST_TINT: [f32; 4] = [0.6, 1.0, 0.6, 0.5];
+/// Colour of a ghost preview that wouldn't be held up by anything.
+const UNSUPPORTED_GHOST_TINT: [f32; 4] = [1.0, 0.4, 0.4, 0.5];
+
+/// Slot actions, in the order of `PieceKind::ALL`.
+const SLOTS: [Action; 6] = [
+    Action::Slot1,
+    Action::Slot2,
+    Action::Slot3,
+    Action::Slot4,
+    Action::Slot5,
+    Action::Slot6,
+];
+/// Brush shapes picked by the first slots in terrain mode.
+const BRUSH_SHAPES: [BrushShape; 3] = [BrushShape::Sphere, BrushShape::Cube, BrushShape::Smooth];
+
+/// Farthest terrain the player can dig into or build onto.
+const EDIT_DISTANCE: f32 = 64.0;
+
+/// Time between two printouts of the frame statistics.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct Stage {
     pipeline: Pipeline,
-    bindings: Bindings,
+    building_pipeline: Pipeline,
+    atlas: TextureAtlas,
+    /// Atlas layer of every terrain `Material`, indexed by its id.
+    material_layers: [f32; MATERIAL_COUNT],
+    /// Atlas layer of every `PieceMaterial`, indexed by its id.
+    piece_layers: [f32; PIECE_MATERIAL_COUNT],
+    /// Texture terrain with its stored top-down texture coordinates instead of triplanarly.
+    use_tex_coords: bool,
     ctx: Box<dyn RenderingBackend>,
-    index_count: i32,
+    chunks: ChunkManager,
+    input: Input,
+    /// Whether the cursor is grabbed and hidden for looking around.
+    mouse_captured: bool,
     camera: Camera,
+    player: Player,
+    brush: Brush,
+    building: Building,
+    /// Whether the mouse places building pieces instead of editing the terrain.
+    build_mode: bool,
+    placement: Placement,
+    /// Whether the ghost preview would be supported if placed.
+    ghost_supported: bool,
+    /// Ghost pose and building revision `ghost_supported` was worked out for.
+    /// Solving support is quadratic in the number of pieces, so it only runs
+    /// again when either changes.
+    ghost_support_key: Option<(Piece, u64)>,
+    /// Terrain under the crosshair, updated every frame.
+    aim: Option<RayHit>,
+    /// Building piece under the crosshair, updated every frame.
+    piece_aim: Option<PieceHit>,
     render_texture: TextureId,
     render_pass: RenderPass,
-    last_frame_time: Instant,
+    /// When frame statistics were last printed.
+    last_report_time: Instant,
+    frames_since_report: u32,
+    /// Building pieces that collapsed since frame statistics were last printed.
+    collapsed_since_report: usize,
+    /// When `update` last fed real time into the timestep.
+    last_update_time: Instant,
+    timestep: FixedTimestep,
+    /// Camera position before the last tick, blended with the current one when
+    /// drawing so movement looks smooth between ticks.
+    previous_camera_position: glam::Vec3,
 }
 
 impl Stage {
-    pub fn new() -> Stage {
+    pub fn new(world_config: WorldGenConfig, tick_rate: f64, use_tex_coords: bool) -> Stage {
         let mut ctx: Box<dyn RenderingBackend> = window::new_rendering_backend();
 
         // Trap the mouse and hide the cursor
-        window::show_mouse(false);
-
-        // Generate scalar field and mesh
-        let grid_size = 512;
-        let scalar_field = generate_scalar_field(grid_size, vec![1, 1, 1]);
-        println!("Scalar field generated!");
-        let threshold = 0.9;
-        let (vertices, indices) = generate_marching_cubes(grid_size, &scalar_field, threshold);
-        println!("Generated Mesh!");
-
-        // Load texture
-        let (image_data, width, height) = load_image_bytes("./assets/textures/grass.png");
-
-        let texture = ctx.new_texture(
-            TextureAccess::Static,
-            TextureSource::Bytes(&image_data),
-            TextureParams {
-                width: width,
-                height: height,
-                format: TextureFormat::RGBA8,
-                wrap: TextureWrap::Repeat,
-                kind: TextureKind::Texture2D,
-                min_filter: FilterMode::Linear,
-                mag_filter: FilterMode::Linear,
-                mipmap_filter: MipmapFilterMode::Nearest,
-                allocate_mipmaps: true,
-                sample_count: 1,
-            },
-        );
-
-        ctx.texture_generate_mipmaps(texture);
-
-        let vertex_buffer = ctx.new_buffer(
-            BufferType::VertexBuffer,
-            BufferUsage::Immutable,
-            BufferSource::slice(&vertices),
-        );
-
-        let index_buffer = ctx.new_buffer(
-            BufferType::IndexBuffer,
-            BufferUsage::Immutable,
-            BufferSource::slice(&indices),
-        );
-
-        let bindings = Bindings {
-            vertex_buffers: vec![vertex_buffer.clone()],
-            index_buffer: index_buffer.clone(),
-            images: vec![texture],
-        };
+        set_mouse_capture(true);
+
+        // Build the texture atlas and find the atlas layer of every terrain material
+        let materials = MaterialRegistry::load(MANIFEST_PATH);
+        let atlas = materials.build_atlas(ctx.as_mut());
+        let material_layers = Material::ALL.map(|material| {
+            materials
+                .layer(material.name())
+                .unwrap_or_else(|| panic!("Material manifest has no \"{}\"", material.name()))
+                as f32
+        });
+        let piece_layers = PieceMaterial::ALL.map(|material| {
+            materials
+                .layer(material.name())
+                .unwrap_or_else(|| panic!("Material manifest has no \"{}\"", material.name()))
+                as f32
+        });
 
         let render_texture = ctx.new_texture(
             TextureAccess::Static,
@@ -100,9 +146,18 @@ impl Stage {
                     fragment: include_str!("../assets/shaders/default/fragment.glsl"),
                 },
                 ShaderMeta {
-                    images: vec!["tex".to_string()],
+                    images: vec!["atlas".to_string()],
                     uniforms: UniformBlockLayout {
-                        uniforms: vec![UniformDesc::new("mvp", UniformType::Mat4)],
+                        uniforms: vec![
+                            UniformDesc::new("mvp", UniformType::Mat4),
+                            UniformDesc::new("sun_direction", UniformType::Float3),
+                            UniformDesc::new("texture_scale", UniformType::Float1),
+                            UniformDesc::new("use_tex_coords", UniformType::Float1),
+                            UniformDesc::new("atlas_grid", UniformType::Float2),
+                            UniformDesc::new("atlas_padding", UniformType::Float1),
+                            UniformDesc::new("material_layers", UniformType::Float1)
+                                .array(MATERIAL_COUNT),
+                        ],
                     },
                 },
             )
@@ -112,7 +167,10 @@ impl Stage {
             &[BufferLayout::default()],
             &[
                 VertexAttribute::new("in_pos", VertexFormat::Float3),
+                VertexAttribute::new("in_normal", VertexFormat::Float3),
                 VertexAttribute::new("in_tex_coord", VertexFormat::Float2),
+                VertexAttribute::new("in_biome", VertexFormat::Float1),
+                VertexAttribute::new("in_material", VertexFormat::Float1),
             ],
             shader,
             PipelineParams {
@@ -123,19 +181,97 @@ impl Stage {
             },
         );
 
+        let building_shader = ctx
+            .new_shader(
+                ShaderSource::Glsl {
+                    vertex: include_str!("../assets/shaders/building/vertex.glsl"),
+                    fragment: include_str!("../assets/shaders/building/fragment.glsl"),
+                },
+                ShaderMeta {
+                    images: vec!["atlas".to_string()],
+                    uniforms: UniformBlockLayout {
+                        uniforms: vec![
+                            UniformDesc::new("mvp", UniformType::Mat4),
+                            UniformDesc::new("sun_direction", UniformType::Float3),
+                            UniformDesc::new("texture_scale", UniformType::Float1),
+                            UniformDesc::new("atlas_grid", UniformType::Float2),
+                            UniformDesc::new("atlas_padding", UniformType::Float1),
+                            UniformDesc::new("tint", UniformType::Float4),
+                            UniformDesc::new("material_layers", UniformType::Float1)
+                                .array(PIECE_MATERIAL_COUNT),
+                        ],
+                    },
+                },
+            )
+            .unwrap();
+
+        // Glass and the ghost preview are see-through
+        let building_pipeline = ctx.new_pipeline(
+            &[BufferLayout::default()],
+            &[
+                VertexAttribute::new("in_pos", VertexFormat::Float3),
+                VertexAttribute::new("in_normal", VertexFormat::Float3),
+                VertexAttribute::new("in_material", VertexFormat::Float1),
+            ],
+            building_shader,
+            PipelineParams {
+                depth_test: Comparison::Less,
+                depth_write: true,
+                primitive_type: PrimitiveType::Triangles,
+                color_blend: Some(BlendState::new(
+                    Equation::Add,
+                    BlendFactor::Value(BlendValue::SourceAlpha),
+                    BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+                )),
+                ..Default::default()
+            },
+        );
+
+        let camera = Camera::new();
+        let player = Player::new(camera.position);
+        let previous_camera_position = camera.position;
+
         Stage {
             pipeline,
-            bindings,
+            building_pipeline,
+            atlas,
+            material_layers,
+            piece_layers,
+            use_tex_coords,
             ctx,
-            index_count: indices.len() as i32,
-            camera: Camera::new(),
+            chunks: ChunkManager::new(world_config),
+            input: Input::load(KEYMAP_PATH, Some(USER_KEYMAP_PATH)),
+            mouse_captured: true,
+            camera,
+            player,
+            brush: Brush {
+                shape: BrushShape::Sphere,
+                radius: 3.0,
+                strength: 1.0,
+            },
+            building: Building::new(),
+            build_mode: false,
+            placement: Placement {
+                kind: PieceKind::Floor,
+                material: PieceMaterial::Wood,
+                rotation: 0,
+            },
+            ghost_supported: false,
+            ghost_support_key: None,
+            aim: None,
+            piece_aim: None,
             render_texture,
             render_pass,
-            last_frame_time: Instant::now(),
+            last_report_time: Instant::now(),
+            frames_since_report: 0,
+            collapsed_since_report: 0,
+            last_update_time: Instant::now(),
+            timestep: FixedTimestep::new(tick_rate),
+            previous_camera_position,
         }
     }
 
-    fn calculate_ortho_mvp(&self) -> [[f32; 4]; 4] {
+    fn calculate_ortho_mvp(&self) -> glam::Mat4 {
         let ortho_projection =
             glam::Mat4::orthographic_rh_gl(-200.0, 200.0, -200.0, 200.0, 0.1, 1000.0);
         let ortho_view = glam::Mat4::look_at_rh(
@@ -143,7 +279,206 @@ impl Stage {
             glam::Vec3::new(0.0, 0.0, 0.0),
             glam::Vec3::new(0.0, 1.0, 0.0),
         );
-        (ortho_projection * ortho_view).to_cols_array_2d()
+        ortho_projection * ortho_view
+    }
+
+    /// Draws the chunks inside the view frustum, and returns how many were drawn
+    /// and how many were culled.
+    fn draw_chunks(&mut self, view_projection: glam::Mat4) -> (usize, usize) {
+        let frustum = Frustum::from_view_projection(view_projection);
+        let mut drawn = 0;
+        let mut culled = 0;
+
+        self.ctx.apply_pipeline(&self.pipeline);
+        for chunk in self.chunks.loaded_chunks() {
+            if !frustum.intersects(&chunk.bounds) {
+                culled += 1;
+                continue;
+            }
+            drawn += 1;
+
+            self.ctx.apply_bindings(&Bindings {
+                vertex_buffers: vec![chunk.vertex_buffer],
+                index_buffer: chunk.index_buffer,
+                images: vec![self.atlas.texture],
+            });
+            self.ctx
+                .apply_uniforms(UniformsSource::table(&shader::UniformsDefault {
+                    mvp: view_projection.to_cols_array_2d(),
+                    sun_direction: SUN_DIRECTION.normalize().to_array(),
+                    texture_scale: TEXTURE_SCALE,
+                    use_tex_coords: self.use_tex_coords as u8 as f32,
+                    atlas_grid: self.atlas.grid,
+                    atlas_padding: self.atlas.padding,
+                    material_layers: self.material_layers,
+                }));
+            self.ctx.draw(0, chunk.index_count, 1);
+        }
+        (drawn, culled)
+    }
+
+    fn draw_building(&mut self, view_projection: glam::Mat4) {
+        self.ctx.apply_pipeline(&self.building_pipeline);
+        let meshes = [
+            (self.building.mesh(), [1.0; 4]),
+            (
+                self.building.ghost_mesh(),
+                if self.ghost_supported {
+                    GHOST_TINT
+                } else {
+                    UNSUPPORTED_GHOST_TINT
+                },
+            ),
+        ];
+        for (mesh, tint) in meshes {
+            let Some(mesh) = mesh.filter(|mesh| mesh.index_count > 0) else {
+                continue;
+            };
+            self.ctx.apply_bindings(&Bindings {
+                vertex_buffers: vec![mesh.vertex_buffer],
+                index_buffer: mesh.index_buffer,
+                images: vec![self.atlas.texture],
+            });
+            self.ctx
+                .apply_uniforms(UniformsSource::table(&shader::UniformsBuilding {
+                    mvp: view_projection.to_cols_array_2d(),
+                    sun_direction: SUN_DIRECTION.normalize().to_array(),
+                    texture_scale: 1.0 / PIECE_SIZE,
+                    atlas_grid: self.atlas.grid,
+                    atlas_padding: self.atlas.padding,
+                    tint,
+                    material_layers: self.piece_layers,
+                }));
+            self.ctx.draw(0, mesh.index_count, 1);
+        }
+    }
+
+    /// Advances the simulation by one fixed tick of `dt` seconds.
+    fn tick(&mut self, dt: f32) {
+        self.previous_camera_position = self.camera.position;
+
+        if self.player.noclip {
+            self.camera.process_input(&self.input, dt);
+            self.player.follow_camera(self.camera.position);
+        } else {
+            let input = MoveInput {
+                direction: self.camera.walk_direction(&self.input),
+                jump: self.input.is_held(Action::Jump),
+            };
+            self.player.update(dt, input, &self.chunks);
+            self.camera.position = self.player.eye_position();
+        }
+
+        self.chunks.update(self.ctx.as_mut(), self.camera.position);
+    }
+
+    /// Reacts to an action being triggered. Actions that are held, like
+    /// movement, are queried from the input every tick instead.
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::Screenshot => self.save_texture_to_png(),
+            Action::ToggleMouseCapture => self.toggle_mouse_capture(),
+            Action::ToggleNoclip => self.player.noclip = !self.player.noclip,
+            Action::ToggleBuildMode => self.build_mode = !self.build_mode,
+            Action::RotatePiece if self.build_mode => self.placement.rotate(),
+            Action::NextMaterial if self.build_mode => {
+                self.placement.material = self.placement.material.next()
+            }
+            Action::Place if self.build_mode => {
+                if let Some(ghost) = self.building.ghost().filter(|_| self.ghost_supported) {
+                    self.building.place(ghost);
+                }
+            }
+            Action::Remove if self.build_mode => {
+                if let Some(piece) = self.aimed_piece() {
+                    self.building.remove(piece.index);
+                    self.collapse_unsupported();
+                }
+            }
+            Action::Dig if !self.build_mode => self.apply_brush(BrushMode::Subtract),
+            Action::Fill if !self.build_mode => self.apply_brush(BrushMode::Add),
+            _ => {
+                if let Some(slot) = SLOTS.iter().position(|slot| *slot == action) {
+                    self.select_slot(slot);
+                }
+            }
+        }
+    }
+
+    /// Grabs or releases the mouse. Looking around only follows the mouse while
+    /// it is captured.
+    fn toggle_mouse_capture(&mut self) {
+        self.mouse_captured = !self.mouse_captured;
+        set_mouse_capture(self.mouse_captured);
+    }
+
+    /// Lets go of the cursor when the window loses it. Clicking into the window
+    /// captures it again.
+    fn release_mouse(&mut self) {
+        self.mouse_captured = false;
+        set_mouse_capture(false);
+        // Key and button releases are missed while away, so nothing should stay held
+        self.input.release_all();
+    }
+
+    /// Picks a piece in build mode, or a brush shape otherwise.
+    fn select_slot(&mut self, slot: usize) {
+        if self.build_mode {
+            self.placement.kind = PieceKind::ALL[slot];
+        } else if let Some(shape) = BRUSH_SHAPES.get(slot) {
+            self.brush.shape = *shape;
+        }
+    }
+
+    fn apply_brush(&mut self, mode: BrushMode) {
+        let Some(hit) = self.aim else {
+            return;
+        };
+        // Centre fills on the air side so they grow out of the surface
+        let center = match mode {
+            BrushMode::Add => hit.position + hit.normal * (self.brush.radius * 0.5),
+            BrushMode::Subtract => hit.position,
+        };
+        self.chunks.apply_brush(
+            self.ctx.as_mut(),
+            BrushStroke::new(self.brush, center, mode),
+        );
+        // Digging can take the ground out from under a building, or the ghost
+        self.collapse_unsupported();
+        self.ghost_support_key = None;
+    }
+
+    /// Removes building pieces that lost their path to the ground.
+    fn collapse_unsupported(&mut self) {
+        let chunks = &self.chunks;
+        self.collapsed_since_report += self.building.collapse_unsupported(|piece| {
+            touches_ground(piece, |position| chunks.holds_up(position))
+        });
+    }
+
+    /// Building piece under the crosshair, unless the terrain is in front of it.
+    fn aimed_piece(&self) -> Option<PieceHit> {
+        self.piece_aim.filter(|piece| {
+            self.aim
+                .is_none_or(|terrain| piece.distance < terrain.distance)
+        })
+    }
+
+    /// Nearest terrain or building surface under the crosshair.
+    fn snap_target(&self) -> Option<SnapTarget> {
+        match (self.aimed_piece(), self.aim) {
+            (Some(piece), _) => Some(SnapTarget {
+                position: piece.position,
+                normal: piece.normal,
+                piece: Some(self.building.piece(piece.index)),
+            }),
+            (None, Some(terrain)) => Some(SnapTarget {
+                position: terrain.position,
+                normal: terrain.normal,
+                piece: None,
+            }),
+            (None, None) => None,
+        }
     }
 
     fn save_texture_to_png(&mut self) {
@@ -171,23 +506,58 @@ impl Stage {
     }
 }
 
+/// Grabs the cursor and hides it, or gives it back.
+fn set_mouse_capture(captured: bool) {
+    window::set_cursor_grab(captured);
+    window::show_mouse(!captured);
+}
+
 impl EventHandler for Stage {
     fn update(&mut self) {
-        self.camera.process_input();
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update_time);
+        self.last_update_time = now;
+        for _ in 0..self.timestep.advance(elapsed) {
+            self.tick(self.timestep.tick_duration());
+        }
+
+        self.aim = self
+            .chunks
+            .raycast(self.camera.position, self.camera.front, EDIT_DISTANCE);
+        self.piece_aim =
+            self.building
+                .raycast(self.camera.position, self.camera.front, EDIT_DISTANCE);
+
+        let ghost = if self.build_mode {
+            let free = self.input.is_held(Action::FreePlacement);
+            self.snap_target()
+                .map(|target| self.building.snap(&self.placement, &target, free))
+        } else {
+            None
+        };
+        let key = ghost.map(|ghost| (ghost, self.building.revision()));
+        if key != self.ghost_support_key {
+            let chunks = &self.chunks;
+            self.ghost_supported = ghost.is_some_and(|ghost| {
+                self.building.would_be_supported(ghost, |piece| {
+                    touches_ground(piece, |position| chunks.holds_up(position))
+                })
+            });
+            self.ghost_support_key = key;
+        }
+        self.building.set_ghost(ghost);
     }
 
     fn draw(&mut self) {
-        let current_time = Instant::now();
-        let frame_time = current_time.duration_since(self.last_frame_time);
-        self.last_frame_time = current_time;
+        self.chunks.upload_finished(self.ctx.as_mut());
+        self.building.upload(self.ctx.as_mut());
 
         let aspect_ratio = screen_size().0 as f32 / screen_size().1 as f32;
 
-        let view = glam::Mat4::look_at_rh(
-            self.camera.position,
-            self.camera.position + self.camera.front,
-            self.camera.up,
-        );
+        let eye = self
+            .previous_camera_position
+            .lerp(self.camera.position, self.timestep.alpha());
+        let view = glam::Mat4::look_at_rh(eye, eye + self.camera.front, self.camera.up);
         let projection =
             glam::Mat4::perspective_rh_gl(45.0_f32.to_radians(), aspect_ratio, 0.1, 2048.0);
 
@@ -199,56 +569,90 @@ impl EventHandler for Stage {
             Some(self.render_pass),
             PassAction::clear_color(0.0, 0.0, 0.0, 1.0),
         );
-        self.ctx.apply_pipeline(&self.pipeline);
-        self.ctx.apply_bindings(&self.bindings);
-        self.ctx
-            .apply_uniforms(UniformsSource::table(&shader::UniformsDefault {
-                mvp: ortho_mvp,
-            }));
-        self.ctx.draw(0, self.index_count, 1);
+        self.draw_chunks(ortho_mvp);
+        self.draw_building(ortho_mvp);
         self.ctx.end_render_pass();
 
         // Render scene to screen
         self.ctx
             .begin_default_pass(PassAction::clear_color(0.4, 0.45, 0.7, 1.0));
-        self.ctx.apply_pipeline(&self.pipeline);
-        self.ctx.apply_bindings(&self.bindings);
-        self.ctx
-            .apply_uniforms(UniformsSource::table(&shader::UniformsDefault {
-                mvp: mvp.to_cols_array_2d(),
-            }));
-        self.ctx.draw(0, self.index_count, 1);
+        let (drawn, culled) = self.draw_chunks(mvp);
+        self.draw_building(mvp);
         self.ctx.end_render_pass();
 
         self.ctx.commit_frame();
 
-        let frame_time_ms = frame_time.as_secs_f64() * 1000.0;
+        // Report once a second, averaged over the frames since the last report
+        self.frames_since_report += 1;
+        let elapsed = self.last_report_time.elapsed();
+        if elapsed < REPORT_INTERVAL {
+            return;
+        }
+        let frame_time_ms = elapsed.as_secs_f64() * 1000.0 / self.frames_since_report as f64;
         let fps = 1000.0 / frame_time_ms;
+        self.last_report_time = Instant::now();
+        self.frames_since_report = 0;
 
-        println!("Frame time: {:.2}ms, FPS: {:.2}", frame_time_ms, fps);
+        let collapsed = match std::mem::take(&mut self.collapsed_since_report) {
+            0 => String::new(),
+            collapsed => format!(", building pieces collapsed: {}", collapsed),
+        };
+        let aim = match &self.aim {
+            Some(hit) => format!(
+                ", aiming at {} {:.1}m away",
+                hit.material.name(),
+                hit.distance
+            ),
+            None => String::new(),
+        };
+        println!(
+            "Frame time: {:.2}ms, FPS: {:.2}, chunks drawn: {}, culled: {}{}{}",
+            frame_time_ms, fps, drawn, culled, collapsed, aim
+        );
     }
 
-    fn key_down_event(&mut self, keycode: KeyCode, _mods: KeyMods, _repeat: bool) {
-        self.camera.keys[keycode as usize] = true;
-
-        if keycode == KeyCode::Space {
-            self.save_texture_to_png();
+    fn key_down_event(&mut self, keycode: KeyCode, _mods: KeyMods, repeat: bool) {
+        if repeat {
+            return;
+        }
+        for action in self.input.press(Binding::Key(keycode)) {
+            self.perform(action);
         }
     }
 
     fn key_up_event(&mut self, keycode: KeyCode, _mods: KeyMods) {
-        self.camera.keys[keycode as usize] = false;
+        self.input.release(Binding::Key(keycode));
     }
 
-    fn mouse_motion_event(&mut self, x: f32, y: f32) {
-        let (last_x, last_y) = self.camera.last_mouse_pos;
+    fn mouse_button_down_event(&mut self, button: MouseButton, _x: f32, _y: f32) {
+        // Clicking into the window captures the mouse again, without digging or building
+        if !self.mouse_captured {
+            self.toggle_mouse_capture();
+            return;
+        }
+        for action in self.input.press(Binding::Mouse(button)) {
+            self.perform(action);
+        }
+    }
 
-        if last_x != 0.0 || last_y != 0.0 {
-            let xoffset = x - last_x;
-            let yoffset = last_y - y;
-            self.camera.process_mouse(xoffset, yoffset);
+    fn mouse_button_up_event(&mut self, button: MouseButton, _x: f32, _y: f32) {
+        self.input.release(Binding::Mouse(button));
+    }
+
+    fn raw_mouse_motion(&mut self, dx: f32, dy: f32) {
+        if self.mouse_captured {
+            self.camera.process_mouse(dx, -dy);
         }
+    }
 
-        self.camera.last_mouse_pos = (x, y);
+    /// Also sent when the window loses focus, e.g. on alt-tab.
+    fn window_minimized_event(&mut self) {
+        self.release_mouse();
+    }
+
+    fn mouse_leave_event(&mut self) {
+        if self.mouse_captured {
+            self.release_mouse();
+        }
     }
 }
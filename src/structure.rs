@@ -0,0 +1,238 @@
+use crate::aabb::Aabb;
+use crate::building::{Piece, PieceMaterial};
+use glam::Vec3;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Gap up to which two pieces still count as touching.
+const CONTACT_TOLERANCE: f32 = 0.05;
+/// Depth below a piece that is checked for terrain holding it up.
+const GROUND_TOLERANCE: f32 = 0.3;
+
+/// How well a piece material carries load. Support is counted in whole units so
+/// every machine computes exactly the same result.
+struct Strength {
+    /// Support of a piece resting on the terrain, and the most any piece of this
+    /// material can have.
+    max_support: u32,
+    /// Support lost to a piece resting on or hanging from another one.
+    vertical_loss: u32,
+    /// Support lost to a piece held up from the side. `max_support /
+    /// horizontal_loss` is how many pieces the material can span.
+    horizontal_loss: u32,
+}
+
+fn strength(material: PieceMaterial) -> Strength {
+    match material {
+        PieceMaterial::Wood => Strength {
+            max_support: 100,
+            vertical_loss: 10,
+            horizontal_loss: 20,
+        },
+        PieceMaterial::Stone => Strength {
+            max_support: 250,
+            vertical_loss: 5,
+            horizontal_loss: 80,
+        },
+        PieceMaterial::Metal => Strength {
+            max_support: 400,
+            vertical_loss: 5,
+            horizontal_loss: 50,
+        },
+        PieceMaterial::Glass => Strength {
+            max_support: 40,
+            vertical_loss: 10,
+            horizontal_loss: 20,
+        },
+    }
+}
+
+/// Support of every piece, in the order of `pieces`. Pieces for which
+/// `grounded` holds get their material's full support; every other piece gets
+/// the best support a touching piece passes on, minus its own loss for the
+/// direction the load travels in. Pieces with no path to the ground end up at 0.
+pub fn solve_support(pieces: &[Piece], grounded: impl Fn(&Piece) -> bool) -> Vec<u32> {
+    let boxes: Vec<Vec<Aabb>> = pieces.iter().map(Piece::boxes).collect();
+
+    let mut support = vec![0; pieces.len()];
+    // Highest support first, lowest index on ties, so the order never depends on
+    // anything but the pieces themselves
+    let mut queue = BinaryHeap::new();
+    for (index, piece) in pieces.iter().enumerate() {
+        if grounded(piece) {
+            support[index] = strength(piece.material).max_support;
+            queue.push((support[index], Reverse(index)));
+        }
+    }
+
+    while let Some((value, Reverse(index))) = queue.pop() {
+        if value < support[index] {
+            continue;
+        }
+        for (neighbour, piece) in pieces.iter().enumerate() {
+            if neighbour == index {
+                continue;
+            }
+            let Some(vertical) = contact(&boxes[index], &boxes[neighbour]) else {
+                continue;
+            };
+            let strength = strength(piece.material);
+            let loss = if vertical {
+                strength.vertical_loss
+            } else {
+                strength.horizontal_loss
+            };
+            let passed = value.saturating_sub(loss).min(strength.max_support);
+            if passed > support[neighbour] {
+                support[neighbour] = passed;
+                queue.push((passed, Reverse(neighbour)));
+            }
+        }
+    }
+
+    support
+}
+
+/// Whether two pieces touch, and if so whether one rests on top of the other
+/// (`true`) or they meet side by side (`false`).
+fn contact(a: &[Aabb], b: &[Aabb]) -> Option<bool> {
+    let mut touching = false;
+    for a in a {
+        for b in b {
+            if !(a.min - CONTACT_TOLERANCE).cmple(b.max).all()
+                || !(b.min - CONTACT_TOLERANCE).cmple(a.max).all()
+            {
+                continue;
+            }
+            if a.max.y <= b.min.y + CONTACT_TOLERANCE || b.max.y <= a.min.y + CONTACT_TOLERANCE {
+                return Some(true);
+            }
+            touching = true;
+        }
+    }
+    touching.then_some(false)
+}
+
+/// Whether a piece rests on or in the terrain, going by the corners and centre
+/// of the bottom of each of its parts, just below it.
+pub fn touches_ground(piece: &Piece, is_solid: impl Fn(Vec3) -> bool) -> bool {
+    piece.boxes().iter().any(|aabb| {
+        let y = aabb.min.y - GROUND_TOLERANCE;
+        let centre = (aabb.min + aabb.max) / 2.0;
+        [
+            Vec3::new(aabb.min.x, y, aabb.min.z),
+            Vec3::new(aabb.max.x, y, aabb.min.z),
+            Vec3::new(aabb.min.x, y, aabb.max.z),
+            Vec3::new(aabb.max.x, y, aabb.max.z),
+            Vec3::new(centre.x, y, centre.z),
+        ]
+        .into_iter()
+        .any(&is_solid)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::{Building, PieceKind, PIECE_SIZE};
+
+    fn piece(kind: PieceKind, material: PieceMaterial, position: Vec3) -> Piece {
+        Piece {
+            kind,
+            material,
+            position,
+            rotation: 0,
+        }
+    }
+
+    fn on_ground(piece: &Piece) -> bool {
+        piece.position.y == 0.0
+    }
+
+    /// Walls stacked `height` high on the ground.
+    fn tower(material: PieceMaterial, height: usize) -> Vec<Piece> {
+        (0..height)
+            .map(|level| {
+                let position = Vec3::Y * level as f32 * PIECE_SIZE;
+                piece(PieceKind::Wall, material, position)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grounded_tower_loses_support_per_level() {
+        let support = solve_support(&tower(PieceMaterial::Wood, 4), on_ground);
+        assert_eq!(support, [100, 90, 80, 70]);
+
+        let support = solve_support(&tower(PieceMaterial::Stone, 3), on_ground);
+        assert_eq!(support, [250, 245, 240]);
+    }
+
+    #[test]
+    fn span_is_limited_per_material() {
+        for (material, supported) in [
+            (PieceMaterial::Wood, 5),
+            (PieceMaterial::Stone, 4),
+            (PieceMaterial::Metal, 8),
+            (PieceMaterial::Glass, 2),
+        ] {
+            // A row of floors held up from the first one only
+            let floors: Vec<Piece> = (0..12)
+                .map(|i| {
+                    let position = Vec3::new(i as f32 * PIECE_SIZE, 1.0, 0.0);
+                    piece(PieceKind::Floor, material, position)
+                })
+                .collect();
+            let support = solve_support(&floors, |floor| floor.position.x == 0.0);
+
+            let held = support.iter().take_while(|&&support| support > 0).count();
+            assert_eq!(held, supported, "{:?}", material);
+            assert!(support[held..].iter().all(|&support| support == 0));
+        }
+    }
+
+    #[test]
+    fn removing_a_piece_collapses_what_it_held() {
+        let mut building = Building::new();
+        for wall in tower(PieceMaterial::Wood, 3) {
+            building.place(wall);
+        }
+        // Floor resting on top of the tower
+        let roof = Vec3::Y * 3.0 * PIECE_SIZE + Vec3::Z * (PIECE_SIZE / 2.0);
+        building.place(piece(PieceKind::Floor, PieceMaterial::Wood, roof));
+        assert_eq!(building.collapse_unsupported(on_ground), 0);
+
+        building.remove(1);
+        assert_eq!(building.collapse_unsupported(on_ground), 2);
+        assert_eq!(building.piece(0), tower(PieceMaterial::Wood, 1)[0]);
+    }
+
+    #[test]
+    fn support_does_not_depend_on_piece_order() {
+        // Two grounded columns of different materials bridged by floors
+        let mut pieces = tower(PieceMaterial::Stone, 2);
+        pieces.extend(tower(PieceMaterial::Wood, 2).into_iter().map(|mut wall| {
+            wall.position.x += 3.0 * PIECE_SIZE;
+            wall
+        }));
+        for i in 0..4 {
+            let position = Vec3::new(i as f32 * PIECE_SIZE, 2.0 * PIECE_SIZE, PIECE_SIZE / 2.0);
+            pieces.push(piece(PieceKind::Floor, PieceMaterial::Metal, position));
+        }
+        let expected = solve_support(&pieces, on_ground);
+        assert!(expected.iter().all(|&support| support > 0));
+
+        for rotation in 1..pieces.len() {
+            let mut order: Vec<usize> = (0..pieces.len()).collect();
+            order.rotate_left(rotation);
+            if rotation % 2 == 1 {
+                order.reverse();
+            }
+            let shuffled: Vec<Piece> = order.iter().map(|&index| pieces[index]).collect();
+            let support = solve_support(&shuffled, on_ground);
+            for (position, &index) in order.iter().enumerate() {
+                assert_eq!(support[position], expected[index]);
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+/// Simulation ticks per second unless `--tick-rate` says otherwise.
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+/// Most ticks simulated in one frame. After a long stall the simulation skips
+/// ahead instead of trying to catch up and stalling again.
+const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// Fixed-timestep clock: real time is collected in an accumulator and handed
+/// out in whole ticks, so the simulation behaves the same at any frame rate.
+pub struct FixedTimestep {
+    /// Length of a tick in seconds.
+    tick: f64,
+    /// Real time not yet simulated, in seconds.
+    accumulator: f64,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f64) -> FixedTimestep {
+        assert!(tick_rate > 0.0, "Tick rate must be positive");
+        FixedTimestep {
+            tick: 1.0 / tick_rate,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Length of a tick in seconds.
+    pub fn tick_duration(&self) -> f32 {
+        self.tick as f32
+    }
+
+    /// Collects `elapsed` real time since the last call and returns how many
+    /// ticks to simulate now.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed.as_secs_f64();
+        let ticks = (self.accumulator / self.tick).floor() as u32;
+        self.accumulator -= ticks as f64 * self.tick;
+        if ticks > MAX_TICKS_PER_FRAME {
+            self.accumulator = 0.0;
+            return MAX_TICKS_PER_FRAME;
+        }
+        ticks
+    }
+
+    /// How far the current moment is between the last simulated tick and the
+    /// next one, in `0..1`. Rendering blends the last two states by it.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.tick) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A rate whose tick length is exact in binary.
+    const TICK_RATE: f64 = 4.0;
+
+    fn seconds(seconds: f64) -> Duration {
+        Duration::from_secs_f64(seconds)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn hands_out_whole_ticks() {
+        let mut timestep = FixedTimestep::new(TICK_RATE);
+        assert_eq!(timestep.tick_duration(), 0.25);
+
+        assert_eq!(timestep.advance(seconds(0.6)), 2);
+        assert_close(timestep.alpha(), 0.4);
+    }
+
+    #[test]
+    fn keeps_the_remainder_for_later_frames() {
+        let mut timestep = FixedTimestep::new(TICK_RATE);
+        assert_eq!(timestep.advance(seconds(0.1)), 0);
+        assert_close(timestep.alpha(), 0.4);
+        assert_eq!(timestep.advance(seconds(0.1)), 0);
+        assert_eq!(timestep.advance(seconds(0.1)), 1);
+        assert_close(timestep.alpha(), 0.2);
+        assert_eq!(timestep.advance(Duration::ZERO), 0);
+        assert_close(timestep.alpha(), 0.2);
+    }
+
+    #[test]
+    fn clamps_ticks_after_a_stall() {
+        let mut timestep = FixedTimestep::new(TICK_RATE);
+        assert_eq!(timestep.advance(seconds(10.1)), MAX_TICKS_PER_FRAME);
+        // The rest of the stall is dropped rather than simulated later
+        assert_close(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(seconds(0.25)), 1);
+    }
+}
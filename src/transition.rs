@@ -0,0 +1,539 @@
+use crate::brush::BrushStroke;
+use crate::chunk::CHUNK_SIZE;
+use crate::data;
+use crate::extras::Vertex;
+use crate::lod::ChunkLod;
+use crate::marching_cubes::{edge_vertex, FieldSample, FIELD_PADDING};
+use crate::scalar_field::ScalarField;
+use glam::{IVec3, Vec3};
+use std::collections::{HashMap, VecDeque};
+
+/// Outward direction of each cell face, in `data::CUBE_FACES` order.
+const FACE_DIRECTIONS: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+
+/// A cell edge, or half of one, by its lower and upper end.
+type Edge = (IVec3, IVec3);
+
+/// Samples at the step of a chunk's finer neighbours, along the faces and edges
+/// it shares with them. Each field is generated like a chunk field, so it holds
+/// exactly the samples the finer chunk has there.
+#[derive(Clone, Default)]
+pub struct TransitionFields {
+    fields: Vec<ScalarField>,
+}
+
+impl TransitionFields {
+    /// Fields for the chunk at `coord`, each made by `build(dimensions, origin,
+    /// step)`. Every field has `FIELD_PADDING` samples around the face or edge
+    /// it covers, for gradients.
+    pub fn new(
+        coord: IVec3,
+        lod: &ChunkLod,
+        build: impl Fn([usize; 3], IVec3, i32) -> ScalarField,
+    ) -> TransitionFields {
+        if !lod.has_finer_neighbours() {
+            return TransitionFields::default();
+        }
+
+        let size = CHUNK_SIZE as i32;
+        let step = lod.step / 2;
+        let padding = FIELD_PADDING as i32 * step;
+        let chunk_min = coord * size;
+        let thickness = 1 + 2 * FIELD_PADDING;
+        let length = CHUNK_SIZE / step as usize + thickness;
+
+        let mut fields = Vec::new();
+        let mut fine_faces = [false; 6];
+        for (face, direction) in FACE_DIRECTIONS.iter().enumerate() {
+            if lod.neighbour_step(*direction) >= lod.step {
+                continue;
+            }
+            fine_faces[face] = true;
+
+            let axis = face / 2;
+            let mut dimensions = [length; 3];
+            dimensions[axis] = thickness;
+            let mut origin = chunk_min - IVec3::splat(padding);
+            origin[axis] += (face % 2) as i32 * size;
+            fields.push(build(dimensions, origin, step));
+        }
+
+        // Edges shared only with finer chunks across an edge or along the
+        // border of a face at this chunk's own step
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for (side_u, side_v) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                if fine_faces[u * 2 + side_u] || fine_faces[v * 2 + side_v] {
+                    continue;
+                }
+                let mut start = chunk_min;
+                start[u] += side_u as i32 * size;
+                start[v] += side_v as i32 * size;
+                let mut end = start;
+                end[axis] += size;
+                if !refined(coord, lod, start, end) {
+                    continue;
+                }
+
+                let mut dimensions = [thickness; 3];
+                dimensions[axis] = length;
+                fields.push(build(dimensions, start - IVec3::splat(padding), step));
+            }
+        }
+
+        TransitionFields { fields }
+    }
+
+    /// Applies a brush stroke to every field it reaches. Returns whether it
+    /// reached any.
+    pub fn apply(&mut self, stroke: &BrushStroke, threshold: f32) -> bool {
+        let mut touched = false;
+        for field in &mut self.fields {
+            if stroke.touches(field) {
+                stroke.apply(field, threshold);
+                touched = true;
+            }
+        }
+        touched
+    }
+
+    /// Sample at a world position, preferring a field that holds it away from
+    /// its padding so the gradient there matches the finer chunk's.
+    fn sample(&self, position: IVec3) -> Option<FieldSample<'_>> {
+        let interior = self
+            .fields
+            .iter()
+            .find_map(|field| sample_at(field, position, FIELD_PADDING));
+        interior.or_else(|| {
+            self.fields
+                .iter()
+                .find_map(|field| sample_at(field, position, 0))
+        })
+    }
+}
+
+/// Sample of `field` at a world position, at least `margin` samples from its
+/// border.
+fn sample_at(field: &ScalarField, position: IVec3, margin: usize) -> Option<FieldSample<'_>> {
+    let local = position - field.origin();
+    let step = field.step();
+    if local.rem_euclid(IVec3::splat(step)) != IVec3::ZERO {
+        return None;
+    }
+    let local = local / step;
+    let dimensions = field.dimensions();
+    let mut index = [0; 3];
+    for axis in 0..3 {
+        let sample = usize::try_from(local[axis]).ok()?;
+        if sample < margin || sample + margin >= dimensions[axis] {
+            return None;
+        }
+        index[axis] = sample;
+    }
+    Some(FieldSample::new(field, index))
+}
+
+/// Whether any chunk whose bounds contain the box from `min` to `max` is meshed
+/// at a finer step than the chunk at `coord`.
+///
+/// A cell edge or face strictly inside a chunk only belongs to that chunk, one
+/// in a chunk face also to the chunk across it, and one along a chunk edge to
+/// all four chunks around it. The box has to lie within the chunks touching
+/// the one at `coord`.
+fn refined(coord: IVec3, lod: &ChunkLod, min: IVec3, max: IVec3) -> bool {
+    let size = CHUNK_SIZE as i32;
+    let first = (max + IVec3::splat(size - 1)).div_euclid(IVec3::splat(size)) - 1;
+    let last = min.div_euclid(IVec3::splat(size));
+    for x in first.x..=last.x {
+        for y in first.y..=last.y {
+            for z in first.z..=last.z {
+                if lod.neighbour_step(IVec3::new(x, y, z) - coord) < lod.step {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// The cells of a chunk that touch a finer chunk, meshed in place of marching
+/// cubes so the two chunks meet without cracks.
+///
+/// Transition cells are regular cells of the chunk whose edges are split in
+/// half wherever a finer chunk also has them, with a sample in the middle of
+/// faces shared with a finer chunk. Both halves of a split edge get their own
+/// vertex, exactly the one the finer chunk has there, while unsplit edges get
+/// exactly the vertex marching cubes gives them. The surface is contoured on
+/// each face of the cell, and the contours are joined into loops and filled.
+///
+/// Unlike Lengyel's Transvoxel cells, these keep the full width of the cell and
+/// don't squash the regular cells behind them. That also closes the seams
+/// between chunks that only share an edge with a finer chunk, which a band of
+/// levels of detail has at every corner.
+pub struct TransitionCells<'a> {
+    field: &'a ScalarField,
+    fine: &'a TransitionFields,
+    coord: IVec3,
+    lod: ChunkLod,
+    threshold: f32,
+}
+
+impl<'a> TransitionCells<'a> {
+    pub fn new(
+        field: &'a ScalarField,
+        fine: &'a TransitionFields,
+        coord: IVec3,
+        lod: ChunkLod,
+        threshold: f32,
+    ) -> TransitionCells<'a> {
+        TransitionCells {
+            field,
+            fine,
+            coord,
+            lod,
+            threshold,
+        }
+    }
+
+    /// Whether the cell at `cell`, counted from the chunk's first cell, is a
+    /// transition cell, to be left out of marching cubes.
+    pub fn contains(&self, cell: [usize; 3]) -> bool {
+        let last = CHUNK_SIZE / self.lod.step as usize - 1;
+        self.lod.has_finer_neighbours()
+            && cell.iter().any(|&index| index == 0 || index == last)
+            && self.is_transition(self.cell_min(cell))
+    }
+
+    /// Meshes every transition cell of the chunk onto the end of the mesh.
+    pub fn mesh(&self, vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+        if !self.lod.has_finer_neighbours() {
+            return;
+        }
+
+        let cells = CHUNK_SIZE / self.lod.step as usize;
+        let mut edge_vertices = HashMap::new();
+        for x in 0..cells {
+            for y in 0..cells {
+                for z in 0..cells {
+                    if self.contains([x, y, z]) {
+                        let min = self.cell_min([x, y, z]);
+                        self.mesh_cell(min, vertices, indices, &mut edge_vertices);
+                    }
+                }
+            }
+        }
+    }
+
+    fn cell_min(&self, [x, y, z]: [usize; 3]) -> IVec3 {
+        self.coord * CHUNK_SIZE as i32 + IVec3::new(x as i32, y as i32, z as i32) * self.lod.step
+    }
+
+    /// Whether the cell with its lowest corner at `min` has any split edge.
+    fn is_transition(&self, min: IVec3) -> bool {
+        data::EDGE_VERTEX_INDICES.iter().any(|&[a, b]| {
+            let (a, b) = (self.corner(min, a), self.corner(min, b));
+            refined(self.coord, &self.lod, a.min(b), a.max(b))
+        })
+    }
+
+    fn corner(&self, min: IVec3, corner: usize) -> IVec3 {
+        let bits = IVec3::new(
+            (corner & 1) as i32,
+            ((corner >> 1) & 1) as i32,
+            ((corner >> 2) & 1) as i32,
+        );
+        min + bits * self.lod.step
+    }
+
+    fn mesh_cell(
+        &self,
+        min: IVec3,
+        vertices: &mut Vec<Vertex>,
+        indices: &mut Vec<u32>,
+        edge_vertices: &mut HashMap<Edge, u32>,
+    ) {
+        // Contour segments on the faces, each running from the edge where the
+        // face's outline passes from solid into air to the edge where it comes
+        // back, seen counter-clockwise from outside. Every crossed edge lies on
+        // two faces that walk it in opposite directions, so it starts one
+        // segment and ends another, and the segments chain into closed loops.
+        let mut segments: Vec<Edge> = Vec::new();
+        let mut ends: Vec<Edge> = Vec::new();
+        for face in 0..6 {
+            for (outline, joins_solid) in self.face_outlines(min, face) {
+                self.contour(&outline, joins_solid, &mut segments, &mut ends);
+            }
+        }
+
+        for edges in chain_segments(segments, ends) {
+            if edges.len() < 3 {
+                continue;
+            }
+            let loop_vertices: Vec<u32> = edges
+                .iter()
+                .map(|&edge| self.edge_vertex(edge, vertices, edge_vertices))
+                .collect();
+            fill_loop(&loop_vertices, vertices, indices);
+        }
+    }
+
+    /// Outlines of the parts of a face to contour separately, counter-clockwise
+    /// from outside, each with how to join an ambiguous outline: `Some(true)`
+    /// to connect its solid corners and `Some(false)` to keep them apart, as
+    /// the marching cubes cell on the other side does.
+    fn face_outlines(&self, min: IVec3, face: usize) -> Vec<(Vec<IVec3>, Option<bool>)> {
+        let corners = data::CUBE_FACES[face].map(|corner| self.corner(min, corner));
+        let step = self.lod.step;
+        let direction = FACE_DIRECTIONS[face];
+
+        if refined(self.coord, &self.lod, corners[0], corners[2]) {
+            // A face shared with a finer chunk splits into the four faces of
+            // its cells there
+            let center = (corners[0] + corners[2]) / 2;
+            return (0..4)
+                .map(|k| {
+                    let (previous, next) = (corners[(k + 3) % 4], corners[(k + 1) % 4]);
+                    let outline = vec![
+                        corners[k],
+                        (corners[k] + next) / 2,
+                        center,
+                        (previous + corners[k]) / 2,
+                    ];
+                    let quarter_min = outline.iter().copied().reduce(IVec3::min).unwrap();
+                    let fine_min = quarter_min + direction.min(IVec3::ZERO) * (step / 2);
+                    let joins_solid = self.joins_solid(fine_min, step / 2, face ^ 1);
+                    (outline, joins_solid)
+                })
+                .collect();
+        }
+
+        let mut outline = Vec::with_capacity(8);
+        for k in 0..4 {
+            let (corner, next) = (corners[k], corners[(k + 1) % 4]);
+            outline.push(corner);
+            if refined(self.coord, &self.lod, corner.min(next), corner.max(next)) {
+                outline.push((corner + next) / 2);
+            }
+        }
+        let neighbour = min + direction * step;
+        let joins_solid = if outline.len() == 4 && !self.is_transition(neighbour) {
+            self.joins_solid(neighbour, step, face ^ 1)
+        } else {
+            None
+        };
+        vec![(outline, joins_solid)]
+    }
+
+    /// Whether marching cubes joins the solid corners of a face of the cell at
+    /// `min` with sides of `step`, if that face is ambiguous.
+    fn joins_solid(&self, min: IVec3, step: i32, face: usize) -> Option<bool> {
+        let mut cube_index = 0;
+        for corner in 0..8 {
+            let bits = IVec3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            if self.value(min + bits * step)? < self.threshold {
+                cube_index |= 1 << corner;
+            }
+        }
+        Some(data::SOLID_JOINING_FACES[cube_index] & (1 << face) != 0)
+    }
+
+    /// Adds the contour segments of one face outline. Ambiguous outlines join
+    /// their solid corners unless `joins_solid` says otherwise; transition
+    /// cells on both sides of a face agree, as they see the same outline.
+    fn contour(
+        &self,
+        outline: &[IVec3],
+        joins_solid: Option<bool>,
+        segments: &mut Vec<Edge>,
+        ends: &mut Vec<Edge>,
+    ) {
+        let solid: Vec<bool> = outline
+            .iter()
+            .map(|&point| {
+                self.value(point)
+                    .is_some_and(|value| value >= self.threshold)
+            })
+            .collect();
+
+        // Crossed edges in outline order, with whether the outline passes into
+        // the air there
+        let mut crossings: Vec<(Edge, bool)> = Vec::new();
+        for k in 0..outline.len() {
+            let next = (k + 1) % outline.len();
+            if solid[k] != solid[next] {
+                let (a, b) = (outline[k], outline[next]);
+                crossings.push(((a.min(b), a.max(b)), solid[k]));
+            }
+        }
+
+        let joins_solid = joins_solid.unwrap_or(true);
+        for (k, &(edge, into_air)) in crossings.iter().enumerate() {
+            let (next, _) = crossings[(k + 1) % crossings.len()];
+            if into_air != joins_solid {
+                continue;
+            }
+            // Cut off the air between this crossing and the next one, or the
+            // solid between them
+            let (start, end) = if joins_solid {
+                (edge, next)
+            } else {
+                (next, edge)
+            };
+            segments.push(start);
+            ends.push(end);
+        }
+    }
+
+    /// Density at a world position on the grid of this chunk or of its finer
+    /// neighbours.
+    fn value(&self, position: IVec3) -> Option<f32> {
+        Some(self.sample(position, self.lod.step)?.value())
+    }
+
+    /// Sample at a world position, from this chunk's field for edges of its own
+    /// step, or from the fine fields for halves of split edges.
+    fn sample(&self, position: IVec3, edge_length: i32) -> Option<FieldSample<'_>> {
+        let step = self.lod.step;
+        if edge_length == step && position.rem_euclid(IVec3::splat(step)) == IVec3::ZERO {
+            sample_at(self.field, position, 0)
+        } else {
+            self.fine.sample(position)
+        }
+    }
+
+    /// Vertex where the surface crosses an edge, shared by every loop through it.
+    fn edge_vertex(
+        &self,
+        edge: Edge,
+        vertices: &mut Vec<Vertex>,
+        edge_vertices: &mut HashMap<Edge, u32>,
+    ) -> u32 {
+        *edge_vertices.entry(edge).or_insert_with(|| {
+            let (lower, upper) = edge;
+            let length = (upper - lower).max_element();
+            let samples = self.sample(lower, length).zip(self.sample(upper, length));
+            let (lower, upper) = samples.expect("fine fields cover every split edge");
+            vertices.push(edge_vertex(lower, upper, self.threshold));
+            vertices.len() as u32 - 1
+        })
+    }
+}
+
+/// Chains contour segments, each running from `segments[k]` to `ends[k]`, into
+/// loops of edges in order.
+///
+/// The segments of a cell always close up, unless a sample is missing or not a
+/// number. A chain that can't be closed is still returned, extended as far as
+/// it goes both ways, so it gets filled as a fan instead of leaving a hole.
+fn chain_segments(mut segments: Vec<Edge>, mut ends: Vec<Edge>) -> Vec<Vec<Edge>> {
+    let mut loops = Vec::new();
+    while let Some(start) = segments.pop() {
+        let mut end = ends.pop().unwrap();
+        let mut edges = VecDeque::from([start]);
+        while end != start {
+            let Some(next) = segments.iter().position(|&edge| edge == end) else {
+                break;
+            };
+            edges.push_back(segments.swap_remove(next));
+            end = ends.swap_remove(next);
+        }
+        if end != start {
+            // Open chain: the rest of it leads up to the start
+            while let Some(previous) = ends.iter().position(|&edge| edge == edges[0]) {
+                ends.swap_remove(previous);
+                edges.push_front(segments.swap_remove(previous));
+            }
+            edges.push_back(end);
+        }
+        loops.push(edges.into());
+    }
+    loops
+}
+
+/// Triangulates a closed loop of vertices, keeping its winding. Loops of more
+/// than three vertices get a fan around an extra vertex at their centre.
+fn fill_loop(loop_vertices: &[u32], vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    if let [a, b, c] = *loop_vertices {
+        indices.extend([a, b, c]);
+        return;
+    }
+
+    let count = loop_vertices.len() as f32;
+    let mut position = Vec3::ZERO;
+    let mut normal = Vec3::ZERO;
+    for &index in loop_vertices {
+        position += Vec3::from(vertices[index as usize].pos);
+        normal += Vec3::from(vertices[index as usize].normal);
+    }
+    let position = (position / count).to_array();
+    let first = &vertices[loop_vertices[0] as usize];
+    let center = Vertex {
+        pos: position,
+        normal: normal.normalize_or_zero().to_array(),
+        tex_coords: [position[0], position[2]],
+        biome: first.biome,
+        material: first.material,
+    };
+    vertices.push(center);
+    let center = vertices.len() as u32 - 1;
+
+    for k in 0..loop_vertices.len() {
+        let next = loop_vertices[(k + 1) % loop_vertices.len()];
+        indices.extend([center, loop_vertices[k], next]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(k: i32) -> Edge {
+        (IVec3::new(k, 0, 0), IVec3::new(k, 1, 0))
+    }
+
+    fn chain(links: &[(i32, i32)]) -> Vec<Vec<i32>> {
+        let segments = links.iter().map(|&(start, _)| edge(start)).collect();
+        let ends = links.iter().map(|&(_, end)| edge(end)).collect();
+        chain_segments(segments, ends)
+            .iter()
+            .map(|edges| edges.iter().map(|(lower, _)| lower.x).collect())
+            .collect()
+    }
+
+    #[test]
+    fn segments_chain_into_closed_loops() {
+        let loops = chain(&[(0, 1), (3, 4), (1, 2), (4, 3), (2, 0)]);
+        assert_eq!(loops, vec![vec![2, 0, 1], vec![3, 4]]);
+    }
+
+    #[test]
+    fn open_chains_are_kept_whole() {
+        // The chain 3 → 4 → 5 → 6 never closes, and is picked up in the middle
+        let loops = chain(&[(0, 1), (3, 4), (1, 2), (5, 6), (2, 0), (4, 5)]);
+        assert_eq!(loops, vec![vec![3, 4, 5, 6], vec![1, 2, 0]]);
+
+        // Filled as a fan that closes it
+        let mut vertices: Vec<Vertex> = [[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0]]
+            .iter()
+            .map(|&[x, z]| Vertex {
+                pos: [x, 1.0, z],
+                normal: [0.0, 1.0, 0.0],
+                tex_coords: [x, z],
+                biome: 0.0,
+                material: 0.0,
+            })
+            .collect();
+        let mut indices = Vec::new();
+        fill_loop(&[0, 1, 2, 3], &mut vertices, &mut indices);
+        assert_eq!(vertices.len(), 5);
+        assert_eq!(vertices[4].pos, [1.0, 1.0, 1.0]);
+        assert_eq!(indices, vec![4, 0, 1, 4, 1, 2, 4, 2, 3, 4, 3, 0]);
+    }
+}
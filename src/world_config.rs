@@ -0,0 +1,244 @@
+use crate::biome::Biome;
+use crate::caves::CaveConfig;
+use crate::density::{DensityFunction, NoiseSource};
+use serde_json::{Map, Value};
+
+/// Parameters of the terrain generator. The same config always produces the
+/// same world.
+#[derive(Clone, Debug)]
+pub struct WorldGenConfig {
+    pub seed: u32,
+    /// Stretches the whole landscape, including the ground height.
+    pub global_scale: f64,
+    /// Frequency of the terrain noise before `global_scale` is applied.
+    pub noise_scale: f64,
+    /// How quickly density drops off above the ground height.
+    pub falloff: f64,
+    pub ground_height: f64,
+    /// Custom terrain shape. When unset, the shape is built from the parameters above.
+    pub density: Option<DensityFunction>,
+    pub caves: CaveConfig,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> WorldGenConfig {
+        WorldGenConfig {
+            seed: 1024,
+            global_scale: 1.2,
+            noise_scale: 0.01,
+            falloff: 0.01,
+            ground_height: 150.0,
+            density: None,
+            caves: CaveConfig::default(),
+        }
+    }
+}
+
+impl WorldGenConfig {
+    /// Loads a config from a JSON file. Missing keys keep their default value.
+    pub fn load(path: &str) -> WorldGenConfig {
+        let json = std::fs::read_to_string(path).expect("Failed to read world config");
+        WorldGenConfig::from_json(&json).expect("Failed to parse world config")
+    }
+
+    pub fn from_json(json: &str) -> Result<WorldGenConfig, String> {
+        let value: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let object = value
+            .as_object()
+            .ok_or("World config must be a JSON object")?;
+
+        let mut config = WorldGenConfig::default();
+        read_u32(object, "seed", &mut config.seed)?;
+        read_f64(object, "global_scale", &mut config.global_scale)?;
+        read_f64(object, "noise_scale", &mut config.noise_scale)?;
+        read_f64(object, "falloff", &mut config.falloff)?;
+        read_f64(object, "ground_height", &mut config.ground_height)?;
+        if let Some(density) = object.get("density") {
+            config.density = Some(DensityFunction::from_json(density)?);
+        }
+        if let Some(caves) = object.get("caves") {
+            let caves = caves.as_object().ok_or("\"caves\" must be a JSON object")?;
+            let cave_config = &mut config.caves;
+            if let Some(enabled) = caves.get("enabled") {
+                cave_config.enabled = enabled.as_bool().ok_or("\"enabled\" must be a boolean")?;
+            }
+            read_f64(caves, "cavern_frequency", &mut cave_config.cavern_frequency)?;
+            read_f64(caves, "cavern_threshold", &mut cave_config.cavern_threshold)?;
+            read_f64(
+                caves,
+                "spaghetti_frequency",
+                &mut cave_config.spaghetti_frequency,
+            )?;
+            read_f64(caves, "spaghetti_width", &mut cave_config.spaghetti_width)?;
+            read_u32(caves, "worms_per_region", &mut cave_config.worms_per_region)?;
+            read_u32(caves, "worm_length", &mut cave_config.worm_length)?;
+            read_f64(caves, "worm_radius", &mut cave_config.worm_radius)?;
+            read_f64(caves, "entrance_chance", &mut cave_config.entrance_chance)?;
+            read_f64(caves, "seal_depth", &mut cave_config.seal_depth)?;
+            if !(0.0..=1.0).contains(&cave_config.entrance_chance) {
+                return Err("\"entrance_chance\" must be between 0 and 1".to_string());
+            }
+            if cave_config.seal_depth <= 0.0 {
+                return Err("\"seal_depth\" must be positive".to_string());
+            }
+        }
+        Ok(config)
+    }
+
+    /// The density function describing the terrain: the custom one if set,
+    /// otherwise a blend of per-biome noise minus a falloff above the ground height.
+    pub fn density_function(&self) -> DensityFunction {
+        if let Some(density) = &self.density {
+            return density.clone();
+        }
+
+        let biomes = Biome::ALL
+            .iter()
+            .map(|biome| {
+                let terrain = biome.terrain();
+                let ground_height =
+                    (self.ground_height + terrain.ground_offset) * self.global_scale;
+                let falloff = self.falloff * terrain.falloff_multiplier * self.global_scale;
+                let frequency = self.noise_scale * terrain.frequency_multiplier * self.global_scale;
+
+                DensityFunction::Add(
+                    Box::new(DensityFunction::Mul(
+                        Box::new(DensityFunction::Constant(terrain.amplitude)),
+                        Box::new(DensityFunction::Noise(NoiseSource::new(
+                            terrain.noise,
+                            0,
+                            frequency,
+                        ))),
+                    )),
+                    Box::new(DensityFunction::YGradient {
+                        from_y: ground_height,
+                        to_y: ground_height + 1.0,
+                        from_value: 0.0,
+                        to_value: -falloff,
+                    }),
+                )
+            })
+            .collect();
+        DensityFunction::BiomeBlend(biomes)
+    }
+
+    /// Applies `--seed`, `--global-scale`, `--noise-scale`, `--falloff` and
+    /// `--ground-height` overrides from the command line.
+    pub fn apply_args(&mut self, args: &[String]) {
+        if let Some(seed) = arg_value(args, "--seed") {
+            self.seed = seed.parse().expect("--seed must be an unsigned integer");
+        }
+        let overrides = [
+            ("--global-scale", &mut self.global_scale),
+            ("--noise-scale", &mut self.noise_scale),
+            ("--falloff", &mut self.falloff),
+            ("--ground-height", &mut self.ground_height),
+        ];
+        for (flag, field) in overrides {
+            if let Some(value) = arg_value(args, flag) {
+                *field = value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("{} must be a number", flag));
+            }
+        }
+    }
+}
+
+/// Returns the argument following `flag`, if present.
+pub fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+fn read_f64(object: &Map<String, Value>, key: &str, field: &mut f64) -> Result<(), String> {
+    if let Some(value) = object.get(key) {
+        *field = value
+            .as_f64()
+            .ok_or_else(|| format!("\"{}\" must be a number", key))?;
+    }
+    Ok(())
+}
+
+fn read_u32(object: &Map<String, Value>, key: &str, field: &mut u32) -> Result<(), String> {
+    if let Some(value) = object.get(key) {
+        *field = value
+            .as_u64()
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| format!("\"{}\" must be an unsigned 32-bit integer", key))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn from_json_overrides_given_keys_only() {
+        let config = WorldGenConfig::from_json(
+            r#"{ "seed": 42, "falloff": 0.5, "caves": { "enabled": false, "worm_length": 3 } }"#,
+        )
+        .unwrap();
+        let default = WorldGenConfig::default();
+
+        assert_eq!(config.seed, 42);
+        assert_eq!(config.falloff, 0.5);
+        assert!(!config.caves.enabled);
+        assert_eq!(config.caves.worm_length, 3);
+        assert_eq!(config.global_scale, default.global_scale);
+        assert_eq!(config.ground_height, default.ground_height);
+        assert_eq!(config.caves.worm_radius, default.caves.worm_radius);
+        assert!(config.density.is_none());
+    }
+
+    #[test]
+    fn from_json_rejects_malformed_input() {
+        for json in [
+            "{ \"seed\": ",
+            "[1, 2]",
+            r#"{ "seed": -1 }"#,
+            r#"{ "seed": 5000000000 }"#,
+            r#"{ "falloff": "steep" }"#,
+            r#"{ "caves": 3 }"#,
+            r#"{ "caves": { "enabled": 1 } }"#,
+            r#"{ "density": { "type": "nothing" } }"#,
+            r#"{ "caves": { "entrance_chance": 1.5 } }"#,
+            r#"{ "caves": { "entrance_chance": -0.1 } }"#,
+            r#"{ "caves": { "seal_depth": 0 } }"#,
+            r#"{ "caves": { "seal_depth": -2.0 } }"#,
+        ] {
+            assert!(WorldGenConfig::from_json(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn apply_args_overrides_flags() {
+        let mut config = WorldGenConfig::default();
+        config.apply_args(&args(&[
+            "gameiguess",
+            "--seed",
+            "9",
+            "--global-scale",
+            "2.5",
+            "--ground-height",
+            "-10",
+        ]));
+
+        assert_eq!(config.seed, 9);
+        assert_eq!(config.global_scale, 2.5);
+        assert_eq!(config.ground_height, -10.0);
+        assert_eq!(config.falloff, WorldGenConfig::default().falloff);
+    }
+
+    #[test]
+    #[should_panic(expected = "--falloff must be a number")]
+    fn apply_args_rejects_non_numbers() {
+        WorldGenConfig::default().apply_args(&args(&["gameiguess", "--falloff", "steep"]));
+    }
+}